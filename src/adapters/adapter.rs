@@ -1,5 +1,6 @@
 use crate::channels::channel::Channel;
 use crate::channels::presence_channel_manager::PresenceMemberInfo;
+use crate::namespace::Namespace;
 use crate::ws_handler::WebSocket;
 use async_trait::async_trait;
 use std::collections::{HashMap, HashSet};
@@ -19,7 +20,7 @@ pub trait Adapter {
         channel: Channel,
         ws_id: &str,
     ) -> Result<usize, ()>;
-    async fn send(self, app_id: &str, channel: &str, data: &str, excepting_id: Option<&str>);
+    async fn send(&mut self, app_id: &str, channel: &str, data: &str, excepting_id: Option<&str>);
     async fn terminate_user_connections(&mut self, app_id: &str, user_id: &str);
     async fn disconnect(&self);
     async fn clear_namespace(&mut self, namespace_id: &str);
@@ -45,7 +46,7 @@ pub trait Adapter {
         app_id: &str,
         channel: &str,
         only_local: bool,
-    ) -> HashMap<String, &WebSocket>;
+    ) -> HashMap<String, WebSocket>;
     async fn get_channel_sockets_count(
         &mut self,
         app_id: &str,
@@ -73,9 +74,11 @@ pub trait Adapter {
     ) -> bool;
     async fn add_user(&mut self, ws: WebSocket);
     async fn remove_user(&mut self, ws: WebSocket);
-    async fn get_user_sockets(
+    async fn get_user_sockets(&mut self, app_id: &str, user_id: &str)
+        -> HashMap<String, WebSocket>;
+    async fn get_watcher_sockets(
         &mut self,
         app_id: &str,
         user_id: &str,
-    ) -> Arc<Mutex<HashSet<&WebSocket>>>;
+    ) -> HashMap<String, WebSocket>;
 }
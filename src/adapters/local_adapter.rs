@@ -2,6 +2,7 @@ use crate::adapters::adapter::Adapter;
 use crate::channels::channel::Channel;
 use crate::channels::presence_channel_manager::PresenceMemberInfo;
 use crate::namespace::Namespace;
+use crate::utils::Utils;
 use crate::ws_handler::WebSocket;
 use async_trait::async_trait;
 use aws_sdk_lambda::config::IntoShared;
@@ -69,24 +70,25 @@ impl Adapter for LocalAdapter {
         namespace.remove_from_channel(ws_id, channel).await
     }
 
-    async fn send(mut self, app_id: &str, channel: &str, data: &str, excepting_id: Option<&str>) {
-        // if channel.contains("#server-to-") {
-        //     let user_id = channel.replace("#server-to-", "");
-        //     let user_sockets = self.get_user_sockets(app_id, &user_id).await;
-        //     for ws in user_sockets.lock().unwrap().deref_mut().into_iter() {
-        //         ws.send_json(serde_json::Value::String(data.to_string()))
-        //             .await;
-        //     }
-        //     return;
-        // }
+    async fn send(&mut self, app_id: &str, channel: &str, data: &str, excepting_id: Option<&str>) {
+        let message: serde_json::Value = match serde_json::from_str(data) {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        if let Some(user_id) = Utils::server_to_user_id(channel) {
+            let user_sockets = self.get_user_sockets(app_id, user_id).await;
+            for (_ws_id, ws) in user_sockets {
+                ws.send_json(message.clone()).await;
+            }
+            return;
+        }
         let namespace = self.get_namespace(app_id).await.unwrap();
         let channel_sockets = namespace.get_channel_sockets(channel).unwrap();
-        for (ws_id, mut ws) in channel_sockets {
+        for (ws_id, ws) in channel_sockets {
             if excepting_id.is_some() && excepting_id.unwrap() == ws_id {
                 continue;
             }
-            ws.send_json(serde_json::Value::String(data.to_string()))
-                .await;
+            ws.send_json(message.clone()).await;
         }
     }
 
@@ -148,7 +150,7 @@ impl Adapter for LocalAdapter {
         app_id: &str,
         channel: &str,
         only_local: bool,
-    ) -> HashMap<String, &WebSocket> {
+    ) -> HashMap<String, WebSocket> {
         let namespace = self.get_namespace(app_id).await.unwrap();
         namespace.get_channel_sockets(channel).unwrap()
     }
@@ -191,29 +193,35 @@ impl Adapter for LocalAdapter {
         only_local: bool,
     ) -> bool {
         let namespace = self.get_namespace(app_id).await.unwrap();
-        namespace.is_in_channel(channel, ws_id).unwrap()
+        namespace.is_in_channel(ws_id, channel).unwrap()
     }
 
     async fn add_user(&mut self, ws: WebSocket) {
-        self.add_socket(ws.app_key.as_ref().unwrap(), ws).await;
+        let namespace = self.get_namespace(&ws.app_id()).await.unwrap();
+        namespace.add_user(ws).unwrap();
     }
 
     async fn remove_user(&mut self, ws: WebSocket) {
-        self.remove_socket(ws.app_key.as_ref().unwrap(), ws.id.as_ref().unwrap())
-            .await;
+        let namespace = self.get_namespace(&ws.app_id()).await.unwrap();
+        namespace.remove_user(ws).unwrap();
     }
 
     async fn get_user_sockets(
         &mut self,
         app_id: &str,
         user_id: &str,
-    ) -> Arc<Mutex<HashSet<&WebSocket>>> {
-        return self
-            .get_namespace(app_id)
-            .await
-            .unwrap()
-            .get_user_sockets(user_id)
-            .unwrap();
+    ) -> HashMap<String, WebSocket> {
+        let namespace = self.get_namespace(app_id).await.unwrap();
+        namespace.get_user_sockets(user_id).unwrap()
+    }
+
+    async fn get_watcher_sockets(
+        &mut self,
+        app_id: &str,
+        user_id: &str,
+    ) -> HashMap<String, WebSocket> {
+        let namespace = self.get_namespace(app_id).await.unwrap();
+        namespace.get_watcher_sockets(user_id).unwrap()
     }
     // fn init(&self) -> Box<dyn AdapterInterface> {
     //     Box::new(self)
@@ -1,2 +1,2 @@
-// pub mod adapter;
-// pub mod local_adapter;
+pub mod adapter;
+pub mod local_adapter;
@@ -1,18 +1,36 @@
+use crate::utils::Utils;
 use aws_sdk_lambda::Config;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, Value};
+use serde_json::{from_str, Map, Value};
 use std::collections::HashMap;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WebhookInterface {
-    url: Option<String>,
-    headers: Option<HashMap<String, String>>,
+    pub(crate) url: Option<String>,
+    pub(crate) headers: Option<HashMap<String, String>>,
     lambda_function: Option<String>,
-    event_types: Vec<String>,
-    filter: Option<HashMap<String, String>>,
+    pub(crate) event_types: Vec<String>,
+    pub(crate) filter: Option<HashMap<String, String>>,
     // lambda: Option<LambdaOptions>,
 }
 
+impl WebhookInterface {
+    /// Applies the optional `channel_name_starts_with` / `channel_name_ends_with` filters.
+    pub fn matches_channel(&self, channel: &str) -> bool {
+        let filter = match &self.filter {
+            Some(filter) => filter,
+            None => return true,
+        };
+        let starts_with = filter
+            .get("channel_name_starts_with")
+            .is_none_or(|prefix| channel.starts_with(prefix.as_str()));
+        let ends_with = filter
+            .get("channel_name_ends_with")
+            .is_none_or(|suffix| channel.ends_with(suffix.as_str()));
+        starts_with && ends_with
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LambdaOptions {
     async_: Option<bool>,
@@ -20,89 +38,67 @@ pub struct LambdaOptions {
     // client_options: Config,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct App {
-    id: String,
-    key: String,
-    secret: String,
-    max_connections: i64,
-    enable_client_messages: bool,
-    enabled: bool,
-    max_backend_events_per_second: i64,
-    max_client_events_per_second: i64,
-    max_read_requests_per_minute: i64,
-    webhooks: Vec<Value>,
-    max_presence_member_size_in_kb: i64,
-    max_channel_name_length: i64,
-    max_event_channel_at_once: i64,
-    max_event_name_length: i64,
-    max_event_payload_in_kb: i64,
-    max_event_batch_size: i64,
-    enable_user_authentication: bool,
-    has_client_event_webhooks: bool,
-    has_channel_occupied_webhooks: bool,
-    has_channel_vacated_webhooks: bool,
-    has_member_added_webhooks: bool,
-    has_member_removed_webhooks: bool,
-    has_cache_missed_webhooks: bool,
+    pub(crate) id: String,
+    pub(crate) key: String,
+    pub(crate) secret: String,
+    pub(crate) max_connections: i64,
+    pub(crate) enable_client_messages: bool,
+    pub(crate) enabled: bool,
+    pub(crate) activity_timeout: u64,
+    pub(crate) max_backend_events_per_second: i64,
+    pub(crate) max_client_events_per_second: i64,
+    pub(crate) max_read_requests_per_minute: i64,
+    pub(crate) webhooks: Vec<Value>,
+    pub(crate) max_presence_members_per_channel: i64,
+    pub(crate) max_presence_member_size_in_kb: i64,
+    pub(crate) max_channel_name_length: i64,
+    pub(crate) max_event_channel_at_once: i64,
+    pub(crate) max_event_name_length: i64,
+    pub(crate) max_event_payload_in_kb: i64,
+    pub(crate) max_event_batch_size: i64,
+    pub(crate) enable_user_authentication: bool,
+    pub(crate) enable_compression: bool,
+    /// Messages shorter than this many bytes are sent uncompressed.
+    pub(crate) compression_threshold: u64,
+    /// Origins browsers may connect from, `*` matching any run of characters. Empty allows all.
+    pub(crate) allowed_origins: Vec<String>,
+    pub(crate) has_client_event_webhooks: bool,
+    pub(crate) has_channel_occupied_webhooks: bool,
+    pub(crate) has_channel_vacated_webhooks: bool,
+    pub(crate) has_member_added_webhooks: bool,
+    pub(crate) has_member_removed_webhooks: bool,
+    pub(crate) has_cache_missed_webhooks: bool,
 }
 
-impl App {
-    fn new(initial_app: HashMap<&str, serde_json::Value>) -> Self {
-        // Example of creating an App instance from a HashMap.
-        // Actual implementation would parse and convert fields as needed.
+/// The settings every configured app starts from. It has no credentials, so it is never served
+/// on its own.
+impl Default for App {
+    fn default() -> Self {
         App {
-            id: initial_app.get("id").unwrap().as_str().unwrap().to_string(),
-            key: initial_app
-                .get("key")
-                .unwrap()
-                .as_str()
-                .unwrap()
-                .to_string(),
-            secret: initial_app
-                .get("secret")
-                .unwrap()
-                .as_str()
-                .unwrap()
-                .to_string(),
-            max_connections: initial_app
-                .get("maxConnections")
-                .unwrap()
-                .as_i64()
-                .unwrap_or(-1),
-            enable_client_messages: initial_app
-                .get("enableClientMessages")
-                .unwrap()
-                .as_bool()
-                .unwrap_or(false),
-            enabled: initial_app
-                .get("enabled")
-                .unwrap()
-                .as_bool()
-                .unwrap_or(true),
-            max_backend_events_per_second: initial_app
-                .get("maxBackendEventsPerSecond")
-                .unwrap()
-                .as_i64()
-                .unwrap_or(-1),
-            max_client_events_per_second: initial_app
-                .get("maxClientEventsPerSecond")
-                .unwrap()
-                .as_i64()
-                .unwrap_or(-1),
-            max_read_requests_per_minute: initial_app
-                .get("maxReadRequestsPerMinute")
-                .unwrap()
-                .as_i64()
-                .unwrap_or(-1),
-            webhooks: Self::transform_potential_json_to_array(initial_app.get("webhooks").unwrap()),
-            max_presence_member_size_in_kb: 0,
-            max_channel_name_length: 0,
-            max_event_channel_at_once: 0,
-            max_event_name_length: 0,
-            max_event_payload_in_kb: 0,
-            max_event_batch_size: 0,
+            id: String::new(),
+            key: String::new(),
+            secret: String::new(),
+            max_connections: -1,
+            enable_client_messages: false,
+            enabled: true,
+            activity_timeout: 120,
+            max_backend_events_per_second: -1,
+            max_client_events_per_second: -1,
+            max_read_requests_per_minute: -1,
+            webhooks: vec![],
+            max_presence_members_per_channel: 100,
+            max_presence_member_size_in_kb: 2,
+            max_channel_name_length: 200,
+            max_event_channel_at_once: 100,
+            max_event_name_length: 200,
+            max_event_payload_in_kb: 100,
+            max_event_batch_size: 10,
             enable_user_authentication: false,
+            enable_compression: false,
+            compression_threshold: 1024,
+            allowed_origins: vec![],
             has_client_event_webhooks: false,
             has_channel_occupied_webhooks: false,
             has_channel_vacated_webhooks: false,
@@ -111,6 +107,115 @@ impl App {
             has_cache_missed_webhooks: false,
         }
     }
+}
+
+impl App {
+    /// Builds an app from its JSON configuration. `id`, `key` and `secret` are required; every
+    /// other key falls back to the default when it is missing or has the wrong type.
+    pub fn new(config: &Map<String, Value>) -> Result<Self, String> {
+        let defaults = App::default();
+        let string = |key: &str| {
+            config
+                .get(key)
+                .and_then(Value::as_str)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+                .ok_or_else(|| format!("The app is missing its `{}`.", key))
+        };
+        let int =
+            |key: &str, default: i64| config.get(key).and_then(Value::as_i64).unwrap_or(default);
+        let uint =
+            |key: &str, default: u64| config.get(key).and_then(Value::as_u64).unwrap_or(default);
+        let boolean =
+            |key: &str, default: bool| config.get(key).and_then(Value::as_bool).unwrap_or(default);
+        let mut app = App {
+            id: string("id")?,
+            key: string("key")?,
+            secret: string("secret")?,
+            max_connections: int("maxConnections", defaults.max_connections),
+            enable_client_messages: boolean(
+                "enableClientMessages",
+                defaults.enable_client_messages,
+            ),
+            enabled: boolean("enabled", defaults.enabled),
+            activity_timeout: uint("activityTimeout", defaults.activity_timeout),
+            max_backend_events_per_second: int(
+                "maxBackendEventsPerSecond",
+                defaults.max_backend_events_per_second,
+            ),
+            max_client_events_per_second: int(
+                "maxClientEventsPerSecond",
+                defaults.max_client_events_per_second,
+            ),
+            max_read_requests_per_minute: int(
+                "maxReadRequestsPerMinute",
+                defaults.max_read_requests_per_minute,
+            ),
+            webhooks: config
+                .get("webhooks")
+                .map(Self::transform_potential_json_to_array)
+                .unwrap_or_default(),
+            max_presence_members_per_channel: int(
+                "maxPresenceMembersPerChannel",
+                defaults.max_presence_members_per_channel,
+            ),
+            max_presence_member_size_in_kb: int(
+                "maxPresenceMemberSizeInKb",
+                defaults.max_presence_member_size_in_kb,
+            ),
+            max_channel_name_length: int("maxChannelNameLength", defaults.max_channel_name_length),
+            max_event_channel_at_once: int(
+                "maxEventChannelsAtOnce",
+                defaults.max_event_channel_at_once,
+            ),
+            max_event_name_length: int("maxEventNameLength", defaults.max_event_name_length),
+            max_event_payload_in_kb: int("maxEventPayloadInKb", defaults.max_event_payload_in_kb),
+            max_event_batch_size: int("maxEventBatchSize", defaults.max_event_batch_size),
+            enable_user_authentication: boolean(
+                "enableUserAuthentication",
+                defaults.enable_user_authentication,
+            ),
+            enable_compression: boolean("enableCompression", defaults.enable_compression),
+            compression_threshold: uint("compressionThreshold", defaults.compression_threshold),
+            allowed_origins: config
+                .get("allowedOrigins")
+                .map(Self::transform_potential_json_to_array)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|origin| origin.as_str().map(str::to_string))
+                .collect(),
+            ..defaults
+        };
+        app.has_client_event_webhooks = app.has_webhooks_for("client_event");
+        app.has_channel_occupied_webhooks = app.has_webhooks_for("channel_occupied");
+        app.has_channel_vacated_webhooks = app.has_webhooks_for("channel_vacated");
+        app.has_member_added_webhooks = app.has_webhooks_for("member_added");
+        app.has_member_removed_webhooks = app.has_webhooks_for("member_removed");
+        app.has_cache_missed_webhooks = app.has_webhooks_for("cache_miss");
+        Ok(app)
+    }
+
+    /// Whether any webhook of the app subscribes to `event_type`.
+    fn has_webhooks_for(&self, event_type: &str) -> bool {
+        self.webhooks.iter().any(|webhook| {
+            webhook
+                .get("event_types")
+                .and_then(Value::as_array)
+                .is_some_and(|event_types| {
+                    event_types.iter().any(|t| t.as_str() == Some(event_type))
+                })
+        })
+    }
+
+    /// Whether a browser on `origin` may connect to the app.
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins.is_empty()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|pattern| Utils::wildcard_match(pattern, origin))
+    }
+
     fn transform_potential_json_to_array(potential_json: &Value) -> Vec<Value> {
         match potential_json {
             Value::Array(arr) => arr.clone(),
@@ -124,3 +229,65 @@ impl App {
         }
     }
 }
+
+#[cfg(test)]
+impl App {
+    /// An app with fixed credentials, for tests that sign or verify requests.
+    pub(crate) fn for_tests() -> Self {
+        App {
+            id: "app-id".to_string(),
+            key: "app-key".to_string(),
+            secret: "app-secret".to_string(),
+            ..App::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(filter: &[(&str, &str)]) -> WebhookInterface {
+        WebhookInterface {
+            url: None,
+            headers: None,
+            lambda_function: None,
+            event_types: vec![],
+            filter: (!filter.is_empty()).then(|| {
+                filter
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect()
+            }),
+        }
+    }
+
+    #[test]
+    fn matches_every_channel_without_a_filter() {
+        assert!(webhook(&[]).matches_channel("private-orders"));
+    }
+
+    #[test]
+    fn matches_channels_by_prefix_and_suffix() {
+        let webhook = webhook(&[
+            ("channel_name_starts_with", "private-"),
+            ("channel_name_ends_with", "-orders"),
+        ]);
+        assert!(webhook.matches_channel("private-team-orders"));
+        assert!(!webhook.matches_channel("presence-team-orders"));
+        assert!(!webhook.matches_channel("private-team-invoices"));
+    }
+
+    #[test]
+    fn flags_the_event_types_the_webhooks_subscribe_to() {
+        let config = serde_json::json!({
+            "id": "app-id",
+            "key": "app-key",
+            "secret": "app-secret",
+            "webhooks": [{ "url": "http://localhost/hooks", "event_types": ["cache_miss"] }],
+        });
+        let app = App::new(config.as_object().unwrap()).unwrap();
+        assert!(app.has_cache_missed_webhooks);
+        assert!(!app.has_member_removed_webhooks);
+    }
+}
@@ -0,0 +1,10 @@
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait CacheManagerInterface {
+    async fn get(&mut self, key: &str) -> Option<String>;
+    /// Seconds left before the value expires, 0 when it never does.
+    async fn ttl(&mut self, key: &str) -> Option<u64>;
+    /// Stores the value for `ttl` seconds, or forever when `ttl` is 0.
+    async fn set(&mut self, key: &str, value: &str, ttl: u64) -> bool;
+}
@@ -0,0 +1,94 @@
+use crate::cache_managers::cache_manager::CacheManagerInterface;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How often `set` sweeps out the entries that expired without being read again.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct CacheEntry {
+    value: String,
+    expires_at: Option<Instant>,
+}
+
+pub struct MemoryCacheManager {
+    memory: HashMap<String, CacheEntry>,
+    last_purge: Instant,
+}
+
+impl MemoryCacheManager {
+    pub fn new() -> Self {
+        MemoryCacheManager {
+            memory: HashMap::new(),
+            last_purge: Instant::now(),
+        }
+    }
+
+    fn purge_expired(&mut self, now: Instant) {
+        self.memory
+            .retain(|_, entry| entry.expires_at.is_none_or(|expires_at| expires_at > now));
+        self.last_purge = now;
+    }
+}
+
+#[async_trait]
+impl CacheManagerInterface for MemoryCacheManager {
+    async fn get(&mut self, key: &str) -> Option<String> {
+        let expired = self
+            .memory
+            .get(key)?
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now());
+        if expired {
+            self.memory.remove(key);
+            return None;
+        }
+        self.memory.get(key).map(|entry| entry.value.clone())
+    }
+
+    async fn ttl(&mut self, key: &str) -> Option<u64> {
+        self.get(key).await?;
+        let expires_at = self.memory.get(key)?.expires_at;
+        Some(expires_at.map_or(0, |expires_at| {
+            expires_at
+                .saturating_duration_since(Instant::now())
+                .as_secs()
+        }))
+    }
+
+    async fn set(&mut self, key: &str, value: &str, ttl: u64) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.last_purge) >= PURGE_INTERVAL {
+            self.purge_expired(now);
+        }
+        let expires_at = (ttl > 0).then(|| now + Duration::from_secs(ttl));
+        self.memory.insert(
+            key.to_string(),
+            CacheEntry {
+                value: value.to_string(),
+                expires_at,
+            },
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn set_purges_entries_that_expired_unread() {
+        let mut cache = MemoryCacheManager::new();
+        cache.set("stale", "event", 1).await;
+        cache.set("kept", "event", 0).await;
+        cache.memory.get_mut("stale").unwrap().expires_at = Some(Instant::now());
+        cache.last_purge -= PURGE_INTERVAL;
+
+        cache.set("fresh", "event", 60).await;
+
+        assert!(!cache.memory.contains_key("stale"));
+        assert_eq!(cache.get("kept").await.as_deref(), Some("event"));
+        assert_eq!(cache.get("fresh").await.as_deref(), Some("event"));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod cache_manager;
+pub mod memory_cache_manager;
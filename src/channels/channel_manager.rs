@@ -1,8 +1,8 @@
 use crate::channels::public_channel_manager::{JoinResponse, LeaveResponse};
 use crate::message::PusherMessage;
-use echoxide::WS;
+use crate::ws_handler::WebSocket;
 
 pub trait ChannelManager {
-    fn join(&self, ws: WS, channel: &str, message: PusherMessage) -> JoinResponse;
-    fn leave(&self, channel: &str) -> LeaveResponse;
+    async fn join(&self, ws: WebSocket, channel: &str, message: PusherMessage) -> JoinResponse;
+    async fn leave(&self, ws: WebSocket, channel: &str) -> LeaveResponse;
 }
@@ -0,0 +1,33 @@
+use crate::channels::channel_manager::ChannelManager;
+use crate::channels::private_channel_manager::PrivateChannelManager;
+use crate::channels::public_channel_manager::{JoinResponse, LeaveResponse};
+use crate::message::PusherMessage;
+use crate::server::Server;
+use crate::ws_handler::WebSocket;
+use std::sync::Weak;
+
+/// `private-encrypted-` channels are authorized exactly like private channels. The payloads are
+/// encrypted end-to-end with a key the server never sees, so they are relayed untouched.
+pub struct EncryptedPrivateChannelManager {
+    private_channel_manager: PrivateChannelManager,
+}
+
+impl EncryptedPrivateChannelManager {
+    pub fn new(server: Weak<Server>) -> Self {
+        Self {
+            private_channel_manager: PrivateChannelManager::new(server),
+        }
+    }
+}
+
+impl ChannelManager for EncryptedPrivateChannelManager {
+    async fn join(&self, ws: WebSocket, channel: &str, message: PusherMessage) -> JoinResponse {
+        self.private_channel_manager
+            .join(ws, channel, message)
+            .await
+    }
+
+    async fn leave(&self, ws: WebSocket, channel: &str) -> LeaveResponse {
+        self.private_channel_manager.leave(ws, channel).await
+    }
+}
@@ -1,6 +1,6 @@
 pub mod channel;
-mod channel_manager;
-mod encrypted_private_channel_manager;
+pub(crate) mod channel_manager;
+pub(crate) mod encrypted_private_channel_manager;
 pub(crate) mod presence_channel_manager;
-mod private_channel_manager;
-mod public_channel_manager;
+pub(crate) mod private_channel_manager;
+pub(crate) mod public_channel_manager;
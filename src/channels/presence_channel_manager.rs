@@ -1,13 +1,222 @@
+use crate::adapters::adapter::Adapter;
+use crate::channels::channel::Channel;
+use crate::channels::channel_manager::ChannelManager;
+use crate::channels::private_channel_manager::PrivateChannelManager;
+use crate::channels::public_channel_manager::{JoinResponse, LeaveResponse};
+use crate::message::PusherMessage;
+use crate::server::Server;
+use crate::utils::Utils;
+use crate::ws_handler::WebSocket;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::sync::Weak;
 
 #[derive(Debug, Clone)]
 pub struct PresenceMemberInfo {
     pub data: HashMap<String, Value>,
 }
 
+#[derive(Debug, Clone)]
 pub struct PresenceMember {
     pub(crate) user_id: String,
     pub(crate) user_info: PresenceMemberInfo,
     pub(crate) socket_id: Option<String>,
 }
+
+impl PresenceMember {
+    /// Parses the `channel_data` a client signed for a presence channel.
+    pub fn from_channel_data(channel_data: &str, socket_id: Option<String>) -> Option<Self> {
+        let data: Value = serde_json::from_str(channel_data).ok()?;
+        let user_id = match data.get("user_id")? {
+            Value::String(user_id) => user_id.clone(),
+            Value::Number(user_id) => user_id.to_string(),
+            _ => return None,
+        };
+        let user_info = data
+            .get("user_info")
+            .and_then(|user_info| user_info.as_object())
+            .map(|user_info| user_info.clone().into_iter().collect())
+            .unwrap_or_default();
+        Some(PresenceMember {
+            user_id,
+            user_info: PresenceMemberInfo { data: user_info },
+            socket_id,
+        })
+    }
+}
+
+pub struct PresenceChannelManager {
+    server: Weak<Server>,
+}
+
+impl PresenceChannelManager {
+    pub fn new(server: Weak<Server>) -> Self {
+        Self { server }
+    }
+}
+
+impl ChannelManager for PresenceChannelManager {
+    async fn join(&self, mut ws: WebSocket, channel: &str, message: PusherMessage) -> JoinResponse {
+        if Utils::restricted_channel_name(channel) {
+            return JoinResponse::restricted_channel_name();
+        }
+        let server = match self.server.upgrade() {
+            Some(server) => server,
+            None => return PrivateChannelManager::unauthorized(),
+        };
+        let app_id = ws.app_id();
+        let app = match ws.app.clone() {
+            Some(app) => app,
+            None => return PrivateChannelManager::unauthorized(),
+        };
+        let socket_id = ws.id.clone().unwrap_or_default();
+        let channel_data = message
+            .data
+            .as_ref()
+            .and_then(|data| data.channel_data.clone())
+            .unwrap_or_default();
+        if !PrivateChannelManager::signature_is_valid(
+            &app,
+            &PrivateChannelManager::passed_signature(&message),
+            &format!("{}:{}:{}", socket_id, channel, channel_data),
+        ) {
+            return PrivateChannelManager::unauthorized();
+        }
+
+        let member = match PresenceMember::from_channel_data(&channel_data, Some(socket_id)) {
+            Some(member) => member,
+            None => {
+                return JoinResponse {
+                    error_message: Some(
+                        "The channel_data must contain a valid user_id.".to_string(),
+                    ),
+                    ..PrivateChannelManager::unauthorized()
+                }
+            }
+        };
+        let member_size_in_kb =
+            Utils::data_to_kilobytes(vec![serde_json::json!(member.user_info.data)]);
+        if member_size_in_kb > app.max_presence_member_size_in_kb as f64 {
+            return JoinResponse::limit_reached(
+                4301,
+                format!(
+                    "The maximum size for a channel member is {} KB.",
+                    app.max_presence_member_size_in_kb
+                ),
+            );
+        }
+
+        // Counting the members and adding this one happen under the same lock, so two users can't
+        // both take the last place in the channel.
+        let mut adapter = server.adapter.lock().await;
+        let members = adapter.get_channel_members(&app_id, channel, false).await;
+        let is_new_member = !members.contains_key(&member.user_id);
+        if is_new_member && members.len() as i64 >= app.max_presence_members_per_channel {
+            return JoinResponse::limit_reached(
+                4100,
+                "The maximum members per presence channel limit was reached".to_string(),
+            );
+        }
+        let socket_id = ws.id.clone();
+        ws.presence_channels
+            .get_or_insert_with(HashMap::new)
+            .insert(channel.to_string(), member.clone());
+        adapter.add_to_channel(&app_id, channel, ws).await;
+        // Other sockets of the same user have already announced the member.
+        if is_new_member {
+            let member_added = serde_json::json!({
+                "event": "pusher_internal:member_added",
+                "channel": channel,
+                "data": {
+                    "user_id": member.user_id,
+                    "user_info": member.user_info.data,
+                },
+            });
+            adapter
+                .send(
+                    &app_id,
+                    channel,
+                    &member_added.to_string(),
+                    socket_id.as_deref(),
+                )
+                .await;
+        }
+        JoinResponse {
+            member: Some(member),
+            ..JoinResponse::joined()
+        }
+    }
+
+    async fn leave(&self, ws: WebSocket, channel: &str) -> LeaveResponse {
+        let member = ws
+            .presence_channels
+            .as_ref()
+            .and_then(|presence_channels| presence_channels.get(channel))
+            .cloned();
+        let mut response = LeaveResponse {
+            left: true,
+            remaining_connections: Some(0),
+            member: None,
+        };
+        let server = match self.server.upgrade() {
+            Some(server) => server,
+            None => return response,
+        };
+        let app_id = ws.app_id();
+        let ws_id = ws.id.clone().unwrap_or_default();
+        // Removing the socket and looking for the user's other sockets happen under the same lock,
+        // so only one of them reports the member as gone.
+        let mut adapter = server.adapter.lock().await;
+        let remaining_connections = adapter
+            .remove_from_channel(&app_id, Channel::String(channel.to_string()), &ws_id)
+            .await
+            .unwrap_or(0);
+        response.remaining_connections = Some(remaining_connections as i64);
+        if let Some(member) = member {
+            let members = adapter.get_channel_members(&app_id, channel, false).await;
+            // The member only leaves once the last of the user's sockets is gone.
+            if !members.contains_key(&member.user_id) {
+                response.member = Some(member);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_string_and_numeric_user_ids() {
+        let member = PresenceMember::from_channel_data(
+            r#"{"user_id": "42", "user_info": {"name": "Ada"}}"#,
+            Some("1.1".to_string()),
+        )
+        .unwrap();
+        assert_eq!(member.user_id, "42");
+        assert_eq!(member.user_info.data["name"], "Ada");
+        assert_eq!(member.socket_id.as_deref(), Some("1.1"));
+
+        let member = PresenceMember::from_channel_data(r#"{"user_id": 42}"#, None).unwrap();
+        assert_eq!(member.user_id, "42");
+        assert!(member.user_info.data.is_empty());
+    }
+
+    #[test]
+    fn rejects_channel_data_without_a_usable_user_id() {
+        for channel_data in [
+            "",
+            "not json",
+            r#"{"user_info": {}}"#,
+            r#"{"user_id": null}"#,
+            r#"{"user_id": ["42"]}"#,
+        ] {
+            assert!(
+                PresenceMember::from_channel_data(channel_data, None).is_none(),
+                "{}",
+                channel_data
+            );
+        }
+    }
+}
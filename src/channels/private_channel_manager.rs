@@ -1,51 +1,105 @@
-// use crate::channels::channel_manager::ChannelManager;
-// use crate::channels::public_channel_manager::{JoinResponse, LeaveResponse};
-// use crate::message::PusherMessage;
-// use crate::{message, utils};
-// use fastwebsockets::{FragmentCollector, WebSocket};
-//
-// struct PrivateChannelManager<S> {
-// }
-//
-// impl<S> PrivateChannelManager<S> {
-//     pub fn new() -> Self {
-//         Self {
-//
-//         }
-//     }
-// }
-//
-// impl<S> ChannelManager<S> for PrivateChannelManager<S> {
-//     fn join(
-//         &self,
-//         ws: FragmentCollector<S>,
-//         channel: &str,
-//         message: message::PusherMessage,
-//     ) -> JoinResponse<S> {
-//         let passed_signature = match message.data {
-//             Some(mut data) => {
-//                 let auth = data.extra.get("auth").unwrap().to_string();
-//                 auth
-//             }
-//             _ => "".to_string(),
-//         };
-//         JoinResponse {
-//             server: ws,
-//             success: false,
-//             channel_connections: None,
-//             auth_error: None,
-//             member: None,
-//             error_message: None,
-//             error_code: None,
-//             type_: None,
-//         }
-//     }
-//
-//     fn leave(&self, channel: &str) -> LeaveResponse {
-//         LeaveResponse {
-//             left: false,
-//             remaining_connections: None,
-//             member: None,
-//         }
-//     }
-// }
+use crate::app::App;
+use crate::channels::channel_manager::ChannelManager;
+use crate::channels::public_channel_manager::{JoinResponse, LeaveResponse, PublicChannelManager};
+use crate::message::PusherMessage;
+use crate::server::Server;
+use crate::token::Token;
+use crate::ws_handler::WebSocket;
+use std::sync::Weak;
+
+pub struct PrivateChannelManager {
+    public_channel_manager: PublicChannelManager,
+}
+
+impl PrivateChannelManager {
+    pub fn new(server: Weak<Server>) -> Self {
+        Self {
+            public_channel_manager: PublicChannelManager::new(server),
+        }
+    }
+
+    /// Checks the `app_key:signature` auth string against the signed data.
+    pub(crate) fn signature_is_valid(app: &App, auth: &str, data_to_sign: &str) -> bool {
+        match auth.split_once(':') {
+            Some((app_key, signature)) if app_key == app.key => {
+                Token::new(&app.key, &app.secret).verify(data_to_sign, signature)
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn unauthorized() -> JoinResponse {
+        JoinResponse {
+            success: false,
+            auth_error: Some(true),
+            member: None,
+            error_message: Some("The connection is unauthorized.".to_string()),
+            error_code: Some(4009),
+            type_: Some("AuthError".to_string()),
+        }
+    }
+
+    pub(crate) fn passed_signature(message: &PusherMessage) -> String {
+        message
+            .data
+            .as_ref()
+            .and_then(|data| data.extra.get("auth"))
+            .and_then(|auth| auth.as_str())
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+impl ChannelManager for PrivateChannelManager {
+    async fn join(&self, ws: WebSocket, channel: &str, message: PusherMessage) -> JoinResponse {
+        let socket_id = ws.id.clone().unwrap_or_default();
+        let authorized = ws.app.as_ref().is_some_and(|app| {
+            Self::signature_is_valid(
+                app,
+                &Self::passed_signature(&message),
+                &format!("{}:{}", socket_id, channel),
+            )
+        });
+        if !authorized {
+            return Self::unauthorized();
+        }
+        self.public_channel_manager.join(ws, channel, message).await
+    }
+
+    async fn leave(&self, ws: WebSocket, channel: &str) -> LeaveResponse {
+        self.public_channel_manager.leave(ws, channel).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_signature_of_the_app() {
+        let signature = Token::new("app-key", "app-secret").sign("1.1:private-channel");
+        let auth = format!("app-key:{}", signature);
+        assert!(PrivateChannelManager::signature_is_valid(
+            &App::for_tests(),
+            &auth,
+            "1.1:private-channel"
+        ));
+    }
+
+    #[test]
+    fn rejects_other_keys_data_and_malformed_auth() {
+        let signature = Token::new("app-key", "app-secret").sign("1.1:private-channel");
+        for (auth, data) in [
+            (format!("other-key:{}", signature), "1.1:private-channel"),
+            (format!("app-key:{}", signature), "1.2:private-channel"),
+            (signature.clone(), "1.1:private-channel"),
+            (String::new(), "1.1:private-channel"),
+        ] {
+            assert!(
+                !PrivateChannelManager::signature_is_valid(&App::for_tests(), &auth, data),
+                "{}",
+                auth
+            );
+        }
+    }
+}
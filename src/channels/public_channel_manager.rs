@@ -1,59 +1,111 @@
+use crate::adapters::adapter::Adapter;
+use crate::channels::channel::Channel;
+use crate::channels::channel_manager::ChannelManager;
+use crate::channels::presence_channel_manager::PresenceMember;
 use crate::message::PusherMessage;
+use crate::server::Server;
 use crate::utils;
-use echoxide::WS;
+use crate::ws_handler::WebSocket;
+use std::sync::Weak;
 
 pub struct JoinResponse {
-    pub(crate) server: WS,
     pub(crate) success: bool,
-    pub(crate) channel_connections: Option<i64>,
     pub(crate) auth_error: Option<bool>,
-    pub(crate) member: Option<bool>,
+    pub(crate) member: Option<PresenceMember>,
     pub(crate) error_message: Option<String>,
     pub(crate) error_code: Option<i64>,
     pub(crate) type_: Option<String>,
 }
 
+impl JoinResponse {
+    /// The socket was added to the channel.
+    pub(crate) fn joined() -> Self {
+        JoinResponse {
+            success: true,
+            auth_error: Some(false),
+            member: None,
+            error_message: None,
+            error_code: None,
+            type_: None,
+        }
+    }
+
+    /// The channel name breaks Pusher's naming conventions.
+    pub(crate) fn restricted_channel_name() -> Self {
+        JoinResponse {
+            success: false,
+            auth_error: Some(true),
+            member: None,
+            error_message: Some("The channel name is not allowed. Read channel conventions: https://pusher.com/docs/channels/using_channels/channels/#channel-naming-conventions".parse().unwrap()),
+            error_code: Some(4009),
+            type_: Some("PusherError".to_string()),
+        }
+    }
+
+    /// Joining would break one of the app's limits.
+    pub(crate) fn limit_reached(error_code: i64, error_message: String) -> Self {
+        JoinResponse {
+            success: false,
+            auth_error: Some(false),
+            member: None,
+            error_message: Some(error_message),
+            error_code: Some(error_code),
+            type_: Some("LimitReached".to_string()),
+        }
+    }
+}
+
 pub struct LeaveResponse {
     pub(crate) left: bool,
     pub(crate) remaining_connections: Option<i64>,
-    pub(crate) member: Option<bool>,
+    pub(crate) member: Option<PresenceMember>,
 }
 
-struct PublicChannelManager {
-    server: WS,
+pub struct PublicChannelManager {
+    server: Weak<Server>,
 }
 
 impl PublicChannelManager {
-    pub fn join(&self, server: WS, channel: &str, message: PusherMessage) -> JoinResponse {
+    pub fn new(server: Weak<Server>) -> Self {
+        Self { server }
+    }
+}
+
+impl ChannelManager for PublicChannelManager {
+    async fn join(&self, ws: WebSocket, channel: &str, _message: PusherMessage) -> JoinResponse {
         if utils::Utils::restricted_channel_name(channel) {
-            JoinResponse {
-                server,
-                success: false,
-                channel_connections: Some(0),
-                auth_error: Some(true),
-                member: Some(false),
-                error_message: Some("The channel name is not allowed. Read channel conventions: https://pusher.com/docs/channels/using_channels/channels/#channel-naming-conventions".parse().unwrap()),
-                error_code: Some(4009),
-                type_: Some("PusherError".to_string()),
-            }
-        } else {
-            JoinResponse {
-                server,
-                success: true,
-                channel_connections: Some(0),
-                auth_error: Some(false),
-                member: Some(false),
-                error_message: None,
-                error_code: None,
-                type_: None,
-            }
+            return JoinResponse::restricted_channel_name();
         }
+        if let Some(server) = self.server.upgrade() {
+            let app_id = ws.app_id();
+            server
+                .adapter
+                .lock()
+                .await
+                .add_to_channel(&app_id, channel, ws)
+                .await;
+        }
+        JoinResponse::joined()
     }
 
-    pub fn leave(&self, channel: &str) -> LeaveResponse {
+    async fn leave(&self, ws: WebSocket, channel: &str) -> LeaveResponse {
+        let remaining_connections = match self.server.upgrade() {
+            Some(server) => {
+                let app_id = ws.app_id();
+                let ws_id = ws.id.clone().unwrap_or_default();
+                server
+                    .adapter
+                    .lock()
+                    .await
+                    .remove_from_channel(&app_id, Channel::String(channel.to_string()), &ws_id)
+                    .await
+                    .unwrap_or(0)
+            }
+            None => 0,
+        };
         LeaveResponse {
             left: true,
-            remaining_connections: Some(0),
+            remaining_connections: Some(remaining_connections as i64),
             member: None,
         }
     }
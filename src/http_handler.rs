@@ -1,22 +1,72 @@
+use crate::app::App;
+use crate::limits::{LimitError, Limits};
 use crate::log::Log;
 use crate::ws_handler::WSHandler;
-use axum::extract::{ConnectInfo, Path, Query};
+use axum::body::Body;
+use axum::extract::{Path, Query, Request};
+use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
-use echoxide::WebSocketUpgrade;
 
 use hyper::{HeaderMap, StatusCode};
 
 // use crate::metrics::metrics_trait::MetricsTrait;
-use crate::message::PusherApiMessage;
+use crate::adapters::adapter::Adapter;
+use crate::message::{PusherApiMessage, PusherApiMessageInfo, PusherBatchMessage};
 use crate::metrics::metrics_trait::MetricsTrait;
 use crate::server::Server;
+use crate::token::Token;
+use crate::utils::Utils;
 use axum::Json;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, to_string_pretty};
+use serde_json::json;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::{Arc, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
 use sysinfo::System;
 
+/// Largest request body the HTTP API reads, the same as axum's default for JSON bodies.
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// An event from the HTTP API that passed validation.
+struct ApiEvent {
+    name: String,
+    channels: Vec<String>,
+    data: String,
+    socket_id: Option<String>,
+    info: Option<String>,
+}
+
+/// Why an HTTP API request could not be served, answered as a JSON `error` message.
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: &str) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<LimitError> for ApiError {
+    fn from(error: LimitError) -> Self {
+        ApiError {
+            status: error.status(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        HttpHandler::send_json(json!({ "error": self.message }), self.status).into_response()
+    }
+}
+
 pub struct HttpHandler {
     pub(crate) server: Weak<Server>,
 }
@@ -31,18 +81,78 @@ impl HttpHandler {
         "OK"
     }
 
-    pub async fn channel(
-        Path(app_id): Path<String>,
-        Path(channel_name): Path<String>,
-    ) -> impl IntoResponse {
-        println!(
-            "WebSocket connection for app {} and channel {}",
-            app_id, channel_name
-        );
+    /// Reports whether a channel is occupied and by how many connections, counted across the
+    /// cluster, along with its members for presence channels and last event for cache channels.
+    pub async fn channel(&self, Path((app_id, channel)): Path<(String, String)>) -> Response {
+        let (server, _app) = match self.find_app(&app_id) {
+            Ok(found) => found,
+            Err(error) => return error.into_response(),
+        };
+        let mut adapter = server.adapter.lock().await;
+        let subscription_count = adapter
+            .get_channel_sockets_count(&app_id, &channel, false)
+            .await;
+        let mut response = json!({
+            "occupied": subscription_count > 0,
+            "subscription_count": subscription_count,
+        });
+        if Utils::is_presence_channel(&channel) {
+            response["user_count"] = json!(
+                adapter
+                    .get_channel_members_count(&app_id, &channel, false)
+                    .await
+            );
+        }
+        drop(adapter);
+        if Utils::is_caching_channel(&channel) {
+            if let Some(event) = server.cached_event(&app_id, &channel).await {
+                let ttl = server.cached_event_ttl(&app_id, &channel).await;
+                response["cache"] = json!({
+                    "data": event["data"],
+                    "ttl": ttl.unwrap_or_default(),
+                });
+            }
+        }
+        HttpHandler::send_json(response, StatusCode::OK).into_response()
     }
 
-    pub async fn channels(Path(app_id): Path<u32>) -> impl IntoResponse {
-        format!("Channels for app {}", app_id)
+    /// Lists the occupied channels of an app, optionally only those starting with a prefix.
+    pub async fn channels(
+        &self,
+        Path(app_id): Path<String>,
+        Query(query): Query<ChannelsQuery>,
+    ) -> Response {
+        let (server, _app) = match self.find_app(&app_id) {
+            Ok(found) => found,
+            Err(error) => return error.into_response(),
+        };
+        let prefix = query.filter_by_prefix.unwrap_or_default();
+        let info = query.info.unwrap_or_default();
+        if info
+            .split(',')
+            .any(|attribute| attribute.trim() == "user_count")
+            && !prefix.starts_with("presence-")
+        {
+            return ApiError::bad_request(
+                "The user_count attribute can only be requested for presence channels.",
+            )
+            .into_response();
+        }
+        let mut adapter = server.adapter.lock().await;
+        let channels = adapter
+            .get_channels_with_sockets_count(&app_id, false)
+            .await;
+        let mut channels_info = serde_json::Map::new();
+        for (channel, connections) in channels {
+            if connections == 0 || !channel.starts_with(&prefix) {
+                continue;
+            }
+            let channel_info = HttpHandler::channel_info(&mut *adapter, &app_id, &channel, &info)
+                .await
+                .map_or(json!({}), |channel_info| json!(channel_info));
+            channels_info.insert(channel, channel_info);
+        }
+        HttpHandler::send_json(json!({ "channels": channels_info }), StatusCode::OK).into_response()
     }
 
     pub async fn ready() -> impl IntoResponse {
@@ -50,8 +160,320 @@ impl HttpHandler {
         "OK"
     }
 
-    pub async fn events(&self, Json(payload): Json<PusherApiMessage>) -> impl IntoResponse {
-        Log::info(to_string_pretty(&payload).unwrap());
+    /// Triggers an event on one or more channels. This is the endpoint the server SDKs publish
+    /// through.
+    pub async fn events(
+        &self,
+        Path(app_id): Path<String>,
+        Json(payload): Json<PusherApiMessage>,
+    ) -> Response {
+        let (server, app) = match self.find_app(&app_id) {
+            Ok(found) => found,
+            Err(error) => return error.into_response(),
+        };
+        let event = match HttpHandler::validate_event(&app, payload) {
+            Ok(event) => event,
+            Err(error) => return error.into_response(),
+        };
+        HttpHandler::publish(&server, &app_id, &event).await;
+
+        let info = event.info.as_deref().unwrap_or_default();
+        let mut adapter = server.adapter.lock().await;
+        let mut channels_info = serde_json::Map::new();
+        for channel in &event.channels {
+            if let Some(channel_info) =
+                HttpHandler::channel_info(&mut *adapter, &app_id, channel, info).await
+            {
+                channels_info.insert(channel.clone(), json!(channel_info));
+            }
+        }
+        if channels_info.is_empty() {
+            return HttpHandler::send_json(json!({}), StatusCode::OK).into_response();
+        }
+        HttpHandler::send_json(json!({ "channels": channels_info }), StatusCode::OK).into_response()
+    }
+
+    /// Triggers several events in one request. Every event is validated before any is sent,
+    /// and they are delivered in the order given.
+    pub async fn batch_events(
+        &self,
+        Path(app_id): Path<String>,
+        Json(payload): Json<PusherBatchMessage>,
+    ) -> Response {
+        let (server, app) = match self.find_app(&app_id) {
+            Ok(found) => found,
+            Err(error) => return error.into_response(),
+        };
+        if let Err(error) = Limits::check_batch_size(&app, payload.batch.len()) {
+            return ApiError::from(error).into_response();
+        }
+        let mut events = Vec::with_capacity(payload.batch.len());
+        for message in payload.batch {
+            // Each event of a batch goes to a single channel, as in Pusher's API.
+            if message.channels.is_some() {
+                return ApiError::bad_request("Batch events take a single channel, not channels.")
+                    .into_response();
+            }
+            match HttpHandler::validate_event(&app, message) {
+                Ok(event) => events.push(event),
+                Err(error) => return error.into_response(),
+            }
+        }
+        for event in &events {
+            HttpHandler::publish(&server, &app_id, event).await;
+        }
+
+        if events.iter().all(|event| event.info.is_none()) {
+            return HttpHandler::send_json(json!({}), StatusCode::OK).into_response();
+        }
+        let mut adapter = server.adapter.lock().await;
+        let mut batch = Vec::with_capacity(events.len());
+        for event in &events {
+            let info = event.info.as_deref().unwrap_or_default();
+            let channel_info =
+                HttpHandler::channel_info(&mut *adapter, &app_id, &event.channels[0], info).await;
+            batch.push(channel_info.map_or(json!({}), |channel_info| json!(channel_info)));
+        }
+        HttpHandler::send_json(json!({ "batch": batch }), StatusCode::OK).into_response()
+    }
+
+    /// Sends an event to every connection of a signed-in user.
+    pub async fn user_events(
+        &self,
+        Path((app_id, user_id)): Path<(String, String)>,
+        Json(payload): Json<PusherApiMessage>,
+    ) -> Response {
+        let name = match payload.name {
+            Some(name) => name,
+            None => {
+                return HttpHandler::send_json(
+                    json!({ "error": "The event name is missing." }),
+                    StatusCode::BAD_REQUEST,
+                )
+                .into_response()
+            }
+        };
+        let (server, app) = match self.find_app(&app_id) {
+            Ok(found) => found,
+            Err(error) => return error.into_response(),
+        };
+        let channel = format!("#server-to-user-{}", user_id);
+        let data = payload.data.as_deref().unwrap_or_default();
+        if let Err(error) = Limits::check_event(&app, std::slice::from_ref(&channel), &name, data) {
+            return ApiError::from(error).into_response();
+        }
+        let message = json!({
+            "event": name,
+            "channel": channel,
+            "data": payload.data,
+        });
+        server
+            .adapter
+            .lock()
+            .await
+            .send(&app_id, &channel, &message.to_string(), None)
+            .await;
+        HttpHandler::send_json(json!({}), StatusCode::OK).into_response()
+    }
+
+    /// Lets through only API requests signed with the app's key and secret, following Pusher's
+    /// HTTP auth scheme.
+    pub async fn authenticate(
+        &self,
+        Path(params): Path<HashMap<String, String>>,
+        Query(query): Query<HashMap<String, String>>,
+        request: Request,
+        next: Next,
+    ) -> Response {
+        let app_id = params.get("app_id").map(String::as_str).unwrap_or_default();
+        let (server, app) = match self.find_app(app_id) {
+            Ok(found) => found,
+            Err(response) => return response.into_response(),
+        };
+        let (parts, body) = request.into_parts();
+        let body = match axum::body::to_bytes(body, MAX_BODY_SIZE).await {
+            Ok(body) => body,
+            Err(_) => {
+                return HttpHandler::send_json(
+                    json!({ "error": "The request body is too large." }),
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                )
+                .into_response()
+            }
+        };
+        let verified = HttpHandler::verify_signature(
+            &app,
+            parts.method.as_str(),
+            parts.uri.path(),
+            &query,
+            &body,
+            server.auth_timestamp_window().as_secs(),
+        );
+        if let Err(error) = verified {
+            return HttpHandler::send_json(json!({ "error": error }), StatusCode::UNAUTHORIZED)
+                .into_response();
+        }
+        next.run(Request::from_parts(parts, Body::from(body))).await
+    }
+
+    fn verify_signature(
+        app: &App,
+        method: &str,
+        path: &str,
+        query: &HashMap<String, String>,
+        body: &[u8],
+        timestamp_window: u64,
+    ) -> Result<(), &'static str> {
+        if query.get("auth_key") != Some(&app.key) {
+            return Err("The auth_key does not match the app.");
+        }
+        if query.get("auth_version").map(String::as_str) != Some("1.0") {
+            return Err("The auth_version must be 1.0.");
+        }
+        let timestamp = query
+            .get("auth_timestamp")
+            .and_then(|timestamp| timestamp.parse::<u64>().ok())
+            .ok_or("The auth_timestamp is missing.")?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        if now.abs_diff(timestamp) > timestamp_window {
+            return Err("The auth_timestamp is outside the allowed window.");
+        }
+        if !body.is_empty() {
+            let body_md5 = format!("{:x}", md5::compute(body));
+            if query.get("body_md5") != Some(&body_md5) {
+                return Err("The body_md5 does not match the request body.");
+            }
+        }
+        let signature = query
+            .get("auth_signature")
+            .ok_or("The auth_signature is missing.")?;
+        // Parameter names are signed in lowercase, sorted, and without the signature itself.
+        let params: HashMap<String, &str> = query
+            .iter()
+            .filter(|(key, _)| key.as_str() != "auth_signature")
+            .map(|(key, value)| (key.to_lowercase(), value.as_str()))
+            .collect();
+        let params: HashMap<&str, &str> = params
+            .iter()
+            .map(|(key, value)| (key.as_str(), *value))
+            .collect();
+        let string_to_sign = format!(
+            "{}\n{}\n{}",
+            method,
+            path,
+            Utils::to_ordered_array(&params).join("&")
+        );
+        if !Token::new(&app.key, &app.secret).verify(&string_to_sign, signature) {
+            return Err("The auth_signature is invalid.");
+        }
+        Ok(())
+    }
+
+    /// Checks an event sent to the HTTP API has a name and channels, and keeps within the limits.
+    fn validate_event(app: &App, payload: PusherApiMessage) -> Result<ApiEvent, ApiError> {
+        let name = payload
+            .name
+            .ok_or_else(|| ApiError::bad_request("The event name is missing."))?;
+        let channels = match (payload.channels, payload.channel) {
+            (Some(channels), _) if !channels.is_empty() => channels,
+            (_, Some(channel)) => vec![channel],
+            _ => return Err(ApiError::bad_request("The channels are missing.")),
+        };
+        let wants_user_count = payload
+            .info
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .any(|attribute| attribute.trim() == "user_count");
+        if wants_user_count
+            && !channels
+                .iter()
+                .all(|channel| Utils::is_presence_channel(channel))
+        {
+            return Err(ApiError::bad_request(
+                "The user_count attribute can only be requested for presence channels.",
+            ));
+        }
+        let data = payload.data.unwrap_or_default();
+        Limits::check_event(app, &channels, &name, &data)?;
+        Ok(ApiEvent {
+            name,
+            channels,
+            data,
+            socket_id: payload.socket_id,
+            info: payload.info,
+        })
+    }
+
+    /// Sends an event to the subscribers of its channels, except the connection that caused it.
+    async fn publish(server: &Server, app_id: &str, event: &ApiEvent) {
+        for channel in &event.channels {
+            let message = json!({
+                "event": event.name,
+                "channel": channel,
+                "data": event.data,
+            });
+            if Utils::is_caching_channel(channel) {
+                server.cache_event(app_id, channel, &message).await;
+            }
+            server
+                .adapter
+                .lock()
+                .await
+                .send(
+                    app_id,
+                    channel,
+                    &message.to_string(),
+                    event.socket_id.as_deref(),
+                )
+                .await;
+        }
+    }
+
+    /// The attributes requested by a comma-separated `info` parameter, or `None` when it asks
+    /// for nothing that applies to the channel.
+    async fn channel_info<A: Adapter>(
+        adapter: &mut A,
+        app_id: &str,
+        channel: &str,
+        info: &str,
+    ) -> Option<PusherApiMessageInfo> {
+        let attributes: Vec<&str> = info.split(',').map(str::trim).collect();
+        let mut channel_info = PusherApiMessageInfo {
+            user_count: None,
+            subscription_count: None,
+        };
+        if attributes.contains(&"user_count") && Utils::is_presence_channel(channel) {
+            let count = adapter
+                .get_channel_members_count(app_id, channel, false)
+                .await;
+            channel_info.user_count = Some(count as u64);
+        }
+        if attributes.contains(&"subscription_count") {
+            let count = adapter
+                .get_channel_sockets_count(app_id, channel, false)
+                .await;
+            channel_info.subscription_count = Some(count as u64);
+        }
+        if channel_info.user_count.is_none() && channel_info.subscription_count.is_none() {
+            return None;
+        }
+        Some(channel_info)
+    }
+
+    /// Looks up the app an API request is addressed to.
+    fn find_app(&self, app_id: &str) -> Result<(Arc<Server>, App), ApiError> {
+        let server = self.server.upgrade().ok_or(ApiError {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            message: "The server is closing.".to_string(),
+        })?;
+        let app = server.find_app_by_id(app_id).ok_or(ApiError {
+            status: StatusCode::NOT_FOUND,
+            message: "The app does not exist.".to_string(),
+        })?;
+        Ok((server, app))
     }
 
     pub fn send_json(
@@ -127,7 +549,129 @@ impl HttpHandler {
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ChannelsQuery {
+    filter_by_prefix: Option<String>,
+    info: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PrometheusQuery {
     json: Option<bool>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 600;
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// Query parameters of a request signed the way the Pusher server SDKs sign them.
+    fn signed_query(
+        method: &str,
+        path: &str,
+        body: &[u8],
+        timestamp: u64,
+        extra: &[(&str, &str)],
+    ) -> HashMap<String, String> {
+        let mut query: HashMap<String, String> = HashMap::from([
+            ("auth_key".to_string(), "app-key".to_string()),
+            ("auth_timestamp".to_string(), timestamp.to_string()),
+            ("auth_version".to_string(), "1.0".to_string()),
+        ]);
+        if !body.is_empty() {
+            query.insert("body_md5".to_string(), format!("{:x}", md5::compute(body)));
+        }
+        for (key, value) in extra {
+            query.insert(key.to_string(), value.to_string());
+        }
+        let mut params: Vec<String> = query
+            .iter()
+            .map(|(key, value)| format!("{}={}", key.to_lowercase(), value))
+            .collect();
+        params.sort();
+        let string_to_sign = format!("{}\n{}\n{}", method, path, params.join("&"));
+        let signature = Token::new("app-key", "app-secret").sign(&string_to_sign);
+        query.insert("auth_signature".to_string(), signature);
+        query
+    }
+
+    fn verify(query: &HashMap<String, String>, body: &[u8]) -> Result<(), &'static str> {
+        HttpHandler::verify_signature(
+            &App::for_tests(),
+            "POST",
+            "/apps/app-id/events",
+            query,
+            body,
+            WINDOW,
+        )
+    }
+
+    #[test]
+    fn accepts_a_signed_request() {
+        let body = br#"{"name":"event","channel":"channel","data":"\u00e9"}"#;
+        let query = signed_query("POST", "/apps/app-id/events", body, now(), &[]);
+        assert_eq!(verify(&query, body), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_bad_signature() {
+        let mut query = signed_query("POST", "/apps/app-id/events", b"", now(), &[]);
+        query.insert("auth_signature".to_string(), "0".repeat(64));
+        assert_eq!(verify(&query, b""), Err("The auth_signature is invalid."));
+
+        let query = signed_query("GET", "/apps/app-id/events", b"", now(), &[]);
+        assert_eq!(verify(&query, b""), Err("The auth_signature is invalid."));
+    }
+
+    #[test]
+    fn rejects_a_stale_timestamp() {
+        let query = signed_query("POST", "/apps/app-id/events", b"", now() - WINDOW - 1, &[]);
+        assert_eq!(
+            verify(&query, b""),
+            Err("The auth_timestamp is outside the allowed window.")
+        );
+    }
+
+    #[test]
+    fn rejects_a_body_that_does_not_match_its_md5() {
+        let query = signed_query("POST", "/apps/app-id/events", b"{}", now(), &[]);
+        assert_eq!(
+            verify(&query, b"{\"tampered\":true}"),
+            Err("The body_md5 does not match the request body.")
+        );
+    }
+
+    #[test]
+    fn hashes_the_raw_body_bytes() {
+        let body = [0xff, 0xfe, 0x7b];
+        let query = signed_query("POST", "/apps/app-id/events", &body, now(), &[]);
+        assert_eq!(verify(&query, &body), Ok(()));
+    }
+
+    #[test]
+    fn signs_parameter_names_in_lowercase() {
+        let query = signed_query(
+            "POST",
+            "/apps/app-id/events",
+            b"",
+            now(),
+            &[("Filter_By_Prefix", "presence-")],
+        );
+        assert_eq!(verify(&query, b""), Ok(()));
+    }
+
+    #[test]
+    fn rejects_other_auth_versions() {
+        let mut query = signed_query("POST", "/apps/app-id/events", b"", now(), &[]);
+        query.insert("auth_version".to_string(), "2.0".to_string());
+        assert_eq!(verify(&query, b""), Err("The auth_version must be 1.0."));
+    }
+}
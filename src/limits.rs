@@ -0,0 +1,191 @@
+use crate::app::App;
+use crate::pusher_error::PusherError;
+use axum::http::StatusCode;
+use std::fmt;
+
+/// A channel or event breaking one of the app's limits. Each variant carries the limit.
+#[derive(Debug, PartialEq)]
+pub enum LimitError {
+    ChannelNameTooLong(i64),
+    TooManyChannels(i64),
+    EventNameTooLong(i64),
+    PayloadTooLarge(i64),
+    BatchTooLarge(i64),
+}
+
+impl LimitError {
+    /// The status the HTTP API answers with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LimitError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::ChannelNameTooLong(max) => write!(
+                f,
+                "The channel name is longer than the allowed {} characters.",
+                max
+            ),
+            LimitError::TooManyChannels(max) => {
+                write!(f, "Cannot broadcast to more than {} channels at once.", max)
+            }
+            LimitError::EventNameTooLong(max) => write!(
+                f,
+                "Event name is too long. Maximum allowed size is {}.",
+                max
+            ),
+            LimitError::PayloadTooLarge(max) => write!(
+                f,
+                "The data content of this event exceeds the allowed maximum ({} kb).",
+                max
+            ),
+            LimitError::BatchTooLarge(max) => {
+                write!(f, "Cannot batch-send more than {} messages at once.", max)
+            }
+        }
+    }
+}
+
+impl From<LimitError> for PusherError {
+    fn from(error: LimitError) -> Self {
+        PusherError::ClientEventRejected(error.to_string())
+    }
+}
+
+/// Checks channels and events against the app's limits, for client events on the socket and
+/// for the HTTP API alike. A negative limit disables its check.
+pub struct Limits;
+
+impl Limits {
+    fn exceeds(value: usize, max: i64) -> bool {
+        max >= 0 && value as u64 > max as u64
+    }
+
+    pub fn check_channel_name(app: &App, channel: &str) -> Result<(), LimitError> {
+        if Self::exceeds(channel.len(), app.max_channel_name_length) {
+            return Err(LimitError::ChannelNameTooLong(app.max_channel_name_length));
+        }
+        Ok(())
+    }
+
+    /// Checks an event triggered on `channels`, with `data` as it will be sent to subscribers.
+    pub fn check_event(
+        app: &App,
+        channels: &[String],
+        name: &str,
+        data: &str,
+    ) -> Result<(), LimitError> {
+        if Self::exceeds(channels.len(), app.max_event_channel_at_once) {
+            return Err(LimitError::TooManyChannels(app.max_event_channel_at_once));
+        }
+        for channel in channels {
+            Self::check_channel_name(app, channel)?;
+        }
+        if Self::exceeds(name.len(), app.max_event_name_length) {
+            return Err(LimitError::EventNameTooLong(app.max_event_name_length));
+        }
+        let max_bytes = app.max_event_payload_in_kb.saturating_mul(1024);
+        if Self::exceeds(data.len(), max_bytes) {
+            return Err(LimitError::PayloadTooLarge(app.max_event_payload_in_kb));
+        }
+        Ok(())
+    }
+
+    pub fn check_batch_size(app: &App, size: usize) -> Result<(), LimitError> {
+        if Self::exceeds(size, app.max_event_batch_size) {
+            return Err(LimitError::BatchTooLarge(app.max_event_batch_size));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> App {
+        App {
+            max_channel_name_length: 10,
+            max_event_channel_at_once: 2,
+            max_event_name_length: 8,
+            max_event_payload_in_kb: 1,
+            max_event_batch_size: 3,
+            ..App::for_tests()
+        }
+    }
+
+    fn channels(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn accepts_events_within_the_limits() {
+        let data = "x".repeat(1024);
+        assert_eq!(
+            Limits::check_event(&app(), &channels(&["a", "b"]), "event", &data),
+            Ok(())
+        );
+        assert_eq!(Limits::check_batch_size(&app(), 3), Ok(()));
+    }
+
+    #[test]
+    fn reports_the_limit_that_was_broken() {
+        let app = app();
+        assert_eq!(
+            Limits::check_channel_name(&app, "channel-name"),
+            Err(LimitError::ChannelNameTooLong(10))
+        );
+        assert_eq!(
+            Limits::check_event(&app, &channels(&["a", "b", "c"]), "event", ""),
+            Err(LimitError::TooManyChannels(2))
+        );
+        assert_eq!(
+            Limits::check_event(&app, &channels(&["channel-name"]), "event", ""),
+            Err(LimitError::ChannelNameTooLong(10))
+        );
+        assert_eq!(
+            Limits::check_event(&app, &channels(&["a"]), "long-event", ""),
+            Err(LimitError::EventNameTooLong(8))
+        );
+        assert_eq!(
+            Limits::check_event(&app, &channels(&["a"]), "event", &"x".repeat(1025)),
+            Err(LimitError::PayloadTooLarge(1))
+        );
+        assert_eq!(
+            Limits::check_batch_size(&app, 4),
+            Err(LimitError::BatchTooLarge(3))
+        );
+    }
+
+    #[test]
+    fn negative_limits_disable_their_check() {
+        let app = App {
+            max_channel_name_length: -1,
+            max_event_payload_in_kb: -1,
+            ..app()
+        };
+        let data = "x".repeat(10_000);
+        assert_eq!(
+            Limits::check_event(&app, &channels(&["a-very-long-channel"]), "event", &data),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn payload_limits_answer_413_and_others_400() {
+        assert_eq!(
+            LimitError::PayloadTooLarge(1).status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            LimitError::TooManyChannels(1).status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(PusherError::from(LimitError::BatchTooLarge(3)).code(), 4301);
+    }
+}
@@ -1,31 +1,34 @@
 mod channels;
 mod http_handler;
+mod limits;
 mod log;
 mod message;
 // mod namespace;
 // mod options;
 mod handle_client;
+mod outbound_queue;
+mod permessage_deflate;
 
 // mod adapters;
 mod adapters;
 mod app;
+mod cache_managers;
 mod metrics;
-// mod namespace;
+mod namespace;
 mod options;
+mod protocol;
+mod pusher_error;
 mod server;
 mod token;
 mod utils;
+mod web_socket;
+mod webhook_sender;
 mod ws_handler;
 
 use crate::server::Server;
-use fastwebsockets::{FragmentCollector, WebSocket};
-use futures_util::StreamExt;
 
 #[tokio::main]
 async fn main() {
-    let incoming = ws.read_frame().await?;
-    // Always returns full messages
-    assert!(incoming.fin);
     let server = Server::new().await;
     server.start().await;
 }
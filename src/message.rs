@@ -11,8 +11,11 @@ pub enum AnyValue {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MessageData {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub channel_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub user_data: Option<String>,
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>, // For additional dynamic fields
@@ -26,6 +29,23 @@ pub struct PusherMessage {
     pub data: Option<MessageData>,
 }
 
+/// A `client-*` event. Its data is relayed to the channel exactly as the client sent it,
+/// whatever its JSON type.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClientEvent {
+    pub event: String,
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub data: serde_json::Value,
+}
+
+/// A frame received from a client.
+#[derive(Debug)]
+pub enum ClientMessage {
+    Pusher(PusherMessage),
+    ClientEvent(ClientEvent),
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct PusherApiMessage {
     pub name: Option<String>,
@@ -33,12 +53,20 @@ pub(crate) struct PusherApiMessage {
     pub channel: Option<String>,
     pub channels: Option<Vec<String>>,
     pub socket_id: Option<String>,
-    pub info: Option<PusherApiMessageInfo>,
+    /// Comma-separated attributes, `user_count` and `subscription_count`, to report per channel.
+    pub info: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct PusherBatchMessage {
+    pub batch: Vec<PusherApiMessage>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PusherApiMessageInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub user_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub subscription_count: Option<u64>,
 }
 
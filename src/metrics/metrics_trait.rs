@@ -3,7 +3,7 @@ use prometheus::proto::MetricFamily;
 use serde_json::Value;
 
 pub trait MetricsTrait {
-    fn mark_new_connection(&mut self, ws: WebSocket);
+    fn mark_new_connection(&self, ws: WebSocket);
     fn mark_disconnection(&self, ws: WebSocket);
     fn mark_api_message(&self, app_id: String, incoming_message: Value, sent_message: Value);
     fn mark_ws_message_sent(&self, app_id: String, sent_message: Value);
@@ -13,6 +13,8 @@ pub trait MetricsTrait {
     fn mark_horizontal_adapter_request_sent(&self, app_id: String);
     fn mark_horizontal_adapter_request_received(&self, app_id: String);
     fn mark_horizontal_adapter_response_received(&self, app_id: String);
+    fn track_outbound_queue_depth(&self, app_id: String, change: i64);
+    fn mark_outbound_messages_dropped(&self, app_id: String, count: usize);
     async fn get_metrics_as_plaintext(&self) -> Result<String, ()>;
     async fn get_metrics_as_json(&self) -> Result<Value, Box<dyn std::error::Error>>;
     fn clear(&self);
@@ -26,6 +26,8 @@ struct PrometheusMetrics {
     horizontal_adapter_sent_requests: CounterVec,
     horizontal_adapter_received_requests: CounterVec,
     horizontal_adapter_received_responses: CounterVec,
+    outbound_queue_depth: GaugeVec,
+    outbound_messages_dropped: CounterVec,
 }
 
 pub struct InfraMetadata {
@@ -46,7 +48,7 @@ pub struct NamespaceTags {
 impl PrometheusMetricsDriver {
     pub fn new(server: Weak<Server>) -> Self {
         let register = Registry::new();
-        Self {
+        let driver = Self {
             server: Weak::new(),
             metrics: PrometheusMetrics {
                 registry: register,
@@ -175,10 +177,55 @@ impl PrometheusMetricsDriver {
                     &["app_id"],
                 )
                 .unwrap(),
+                outbound_queue_depth: GaugeVec::new(
+                    prometheus::Opts::new(
+                        "outbound_queue_depth",
+                        "Number of messages waiting to be written to sockets",
+                    ),
+                    &["app_id"],
+                )
+                .unwrap(),
+                outbound_messages_dropped: CounterVec::new(
+                    prometheus::Opts::new(
+                        "outbound_messages_dropped",
+                        "Total number of messages dropped because a socket's outbound queue was full",
+                    ),
+                    &["app_id"],
+                )
+                .unwrap(),
             },
             infra_metadata: InfraMetadata {
                 data: Default::default(),
             },
+        };
+        driver.register();
+        driver
+    }
+
+    fn register(&self) {
+        let metrics = &self.metrics;
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.connected_sockets.clone()),
+            Box::new(metrics.new_connections_total.clone()),
+            Box::new(metrics.new_disconnections_total.clone()),
+            Box::new(metrics.socket_bytes_received.clone()),
+            Box::new(metrics.socket_bytes_transmitted.clone()),
+            Box::new(metrics.ws_messages_received.clone()),
+            Box::new(metrics.ws_messages_sent.clone()),
+            Box::new(metrics.http_bytes_received.clone()),
+            Box::new(metrics.http_bytes_transmitted.clone()),
+            Box::new(metrics.http_calls_received.clone()),
+            Box::new(metrics.horizontal_adapter_resolve_time.clone()),
+            Box::new(metrics.horizontal_adapter_resolved_promises.clone()),
+            Box::new(metrics.horizontal_adapter_uncomplete_promises.clone()),
+            Box::new(metrics.horizontal_adapter_sent_requests.clone()),
+            Box::new(metrics.horizontal_adapter_received_requests.clone()),
+            Box::new(metrics.horizontal_adapter_received_responses.clone()),
+            Box::new(metrics.outbound_queue_depth.clone()),
+            Box::new(metrics.outbound_messages_dropped.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
         }
     }
     pub fn get_tags(&self, app_id: String) -> NamespaceTags {
@@ -190,8 +237,8 @@ impl PrometheusMetricsDriver {
 }
 
 impl MetricsTrait for PrometheusMetricsDriver {
-    fn mark_new_connection(&mut self, ws: WebSocket) {
-        let app_id = ws.app_key.unwrap();
+    fn mark_new_connection(&self, ws: WebSocket) {
+        let app_id = ws.app_id();
         self.metrics
             .connected_sockets
             .with_label_values(&[&app_id.as_str()])
@@ -203,7 +250,7 @@ impl MetricsTrait for PrometheusMetricsDriver {
     }
 
     fn mark_disconnection(&self, ws: WebSocket) {
-        let app_id = ws.app_key.unwrap();
+        let app_id = ws.app_id();
         self.metrics
             .connected_sockets
             .with_label_values(&[&app_id.as_str()])
@@ -293,6 +340,20 @@ impl MetricsTrait for PrometheusMetricsDriver {
             .inc();
     }
 
+    fn track_outbound_queue_depth(&self, app_id: String, change: i64) {
+        self.metrics
+            .outbound_queue_depth
+            .with_label_values(&[app_id.as_str()])
+            .add(change as f64);
+    }
+
+    fn mark_outbound_messages_dropped(&self, app_id: String, count: usize) {
+        self.metrics
+            .outbound_messages_dropped
+            .with_label_values(&[app_id.as_str()])
+            .inc_by(count as f64);
+    }
+
     async fn get_metrics_as_plaintext(&self) -> Result<String, ()> {
         let encoder = TextEncoder::new();
         let metric_families = self.metrics.registry.gather();
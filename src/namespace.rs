@@ -1,5 +1,5 @@
 use crate::channels::channel::Channel;
-use crate::channels::presence_channel_manager::{PresenceMember, PresenceMemberInfo};
+use crate::channels::presence_channel_manager::PresenceMemberInfo;
 use crate::ws_handler::WebSocket;
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
@@ -7,6 +7,8 @@ use std::sync::{Arc, Mutex};
 pub struct Namespace {
     pub channels: HashMap<String, HashSet<String>>,
     pub users: HashMap<String, HashSet<String>>,
+    /// Watched user id to the ids of the sockets whose watchlist contains it.
+    pub watchers: HashMap<String, HashSet<String>>,
     pub app_id: String,
     pub sockets: Arc<Mutex<HashMap<String, WebSocket>>>,
 }
@@ -16,6 +18,7 @@ impl Namespace {
         Namespace {
             channels: HashMap::new(),
             users: HashMap::new(),
+            watchers: HashMap::new(),
             app_id,
             sockets: Arc::new(Mutex::new(HashMap::new())),
         }
@@ -27,12 +30,12 @@ impl Namespace {
 
     pub fn add_socket(&mut self, ws: WebSocket) -> Result<bool, ()> {
         let (ws_id, ws) = (ws.id.clone().unwrap(), ws);
-        self.sockets.insert(ws_id, ws);
+        self.sockets.lock().unwrap().insert(ws_id, ws);
         Ok(true)
     }
 
     pub fn remove_socket(&mut self, id: String) -> Result<bool, ()> {
-        self.sockets.remove(&id);
+        self.sockets.lock().unwrap().remove(&id);
         Ok(true)
     }
     pub(crate) async fn remove_from_channel(
@@ -98,7 +101,24 @@ impl Namespace {
     }
 
     pub fn add_user(&mut self, ws: WebSocket) -> Result<(), ()> {
+        let ws_id = ws.id.clone().expect("Failed to get ws id");
+        // A socket that signs in again stops watching its previous watchlist.
+        let previous_user = self
+            .sockets
+            .lock()
+            .unwrap()
+            .get(&ws_id)
+            .and_then(|previous| previous.user.clone());
+        if let Some(previous_user) = previous_user {
+            self.unwatch(&ws_id, &previous_user.watchlist());
+        }
         let user = ws.user.unwrap();
+        for watched_id in user.watchlist() {
+            self.watchers
+                .entry(watched_id)
+                .or_default()
+                .insert(ws_id.clone());
+        }
         let user_id = user.id;
         if let Some(channels) = self.users.get_mut(&user_id) {
             channels.insert(ws.id.clone().expect("Failed to get ws id"));
@@ -113,35 +133,40 @@ impl Namespace {
     pub fn remove_user(&mut self, ws: WebSocket) -> Result<(), ()> {
         let user = ws.user;
         if let Some(user) = user {
+            let ws_id = ws.id.clone().unwrap();
+            self.unwatch(&ws_id, &user.watchlist());
             let user_id = user.id;
-            if let Some(user) = self.users.get_mut(&user_id) {
-                self.users.remove(&ws.id.clone().unwrap());
-            }
-            if let Some(user) = self.users.get_mut(&user_id) {
-                if user.is_empty() {
+            if let Some(user_sockets) = self.users.get_mut(&user_id) {
+                user_sockets.remove(&ws_id);
+                if user_sockets.is_empty() {
                     self.users.remove(&user_id);
                 }
             }
         }
         Ok(())
     }
-    pub fn get_channel_sockets(
-        &self,
-        channel: &str,
-    ) -> Result<Arc<Mutex<HashMap<String, &WebSocket>>>, ()> {
-        let channels = self.channels.clone();
-        if channels.get(channel).is_none() {
-            Ok(Arc::new(Mutex::new(HashMap::new())))
-        } else {
-            let ws_ids = channels.get(channel).unwrap();
-            let mut sockets = HashMap::new();
+
+    fn unwatch(&mut self, ws_id: &str, watchlist: &[String]) {
+        for watched_id in watchlist {
+            if let Some(watchers) = self.watchers.get_mut(watched_id) {
+                watchers.remove(ws_id);
+                if watchers.is_empty() {
+                    self.watchers.remove(watched_id);
+                }
+            }
+        }
+    }
+    pub fn get_channel_sockets(&self, channel: &str) -> Result<HashMap<String, WebSocket>, ()> {
+        let mut sockets = HashMap::new();
+        if let Some(ws_ids) = self.channels.get(channel) {
+            let all_sockets = self.sockets.lock().unwrap();
             for ws_id in ws_ids.iter() {
-                if let Some(ws) = self.sockets.lock().unwrap().get(ws_id) {
-                    sockets.insert(ws_id.clone(), ws);
+                if let Some(ws) = all_sockets.get(ws_id) {
+                    sockets.insert(ws_id.clone(), ws.clone());
                 }
             }
-            Ok(Arc::clone(&Arc::new(Mutex::new(sockets))))
         }
+        Ok(sockets)
     }
     pub(crate) fn get_channel_sockets_count(&self, p0: &str) -> Result<usize, ()> {
         let channels = self.channels.clone();
@@ -159,19 +184,13 @@ impl Namespace {
     ) -> Result<HashMap<String, PresenceMemberInfo>, ()> {
         let sockets = self.get_channel_sockets(channel).unwrap();
         let mut members = HashMap::<String, PresenceMemberInfo>::new();
-        for (ws_id, ws) in sockets {
-            let channel = ws.presence_channels.as_ref().unwrap().get(channel);
-            if channel.is_some() {
-                let member: PresenceMember = PresenceMember {
-                    user_id: channel.unwrap().data.get("user_id").unwrap().to_string(),
-                    user_info: PresenceMemberInfo {
-                        data: channel.unwrap().data.clone(),
-                    },
-                    socket_id: Option::from(
-                        channel.unwrap().data.get("socket_id").unwrap().to_string(),
-                    ),
-                };
-                members.insert(member.user_id, member.user_info);
+        for (_ws_id, ws) in sockets {
+            let member = ws
+                .presence_channels
+                .as_ref()
+                .and_then(|presence_channels| presence_channels.get(channel));
+            if let Some(member) = member {
+                members.insert(member.user_id.clone(), member.user_info.clone());
             }
         }
         Ok(members)
@@ -187,12 +206,26 @@ impl Namespace {
         }
         Ok(socket_ids)
     }
-    pub fn get_user_sockets(&self, user_id: &str) -> Result<Arc<Mutex<HashSet<&WebSocket>>>, ()> {
-        let mut sockets = Arc::new(Mutex::new(HashSet::new()));
+    pub fn get_user_sockets(&self, user_id: &str) -> Result<HashMap<String, WebSocket>, ()> {
+        let mut sockets = HashMap::new();
         if let Some(ws_ids) = self.users.get(user_id) {
+            let all_sockets = self.sockets.lock().unwrap();
+            for ws_id in ws_ids.iter() {
+                if let Some(ws) = all_sockets.get(ws_id) {
+                    sockets.insert(ws_id.clone(), ws.clone());
+                }
+            }
+        }
+        Ok(sockets)
+    }
+    /// Sockets of signed-in users whose watchlist contains `user_id`.
+    pub fn get_watcher_sockets(&self, user_id: &str) -> Result<HashMap<String, WebSocket>, ()> {
+        let mut sockets = HashMap::new();
+        if let Some(ws_ids) = self.watchers.get(user_id) {
+            let all_sockets = self.sockets.lock().unwrap();
             for ws_id in ws_ids.iter() {
-                if let Some(ws) = self.sockets.get(ws_id) {
-                    sockets.lock().unwrap().insert(ws);
+                if let Some(ws) = all_sockets.get(ws_id) {
+                    sockets.insert(ws_id.clone(), ws.clone());
                 }
             }
         }
@@ -212,12 +245,7 @@ impl Namespace {
                         },
                     }))
                     .await;
-                    if ws
-                        .ws
-                        .close((4201u16, "You got disconnected by the app."))
-                        .await
-                        .is_ok()
-                    {}
+                    ws.stop(4201, "You got disconnected by the app.").await;
                 }
             }
         }
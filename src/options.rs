@@ -1,5 +1,9 @@
 use crate::app::App;
+use crate::log::Log;
+use crate::outbound_queue::SlowConsumerPolicy;
 use serde_json::Value;
+use std::str::FromStr;
+use std::{env, fs};
 
 pub struct Redis {
     pub host: String,
@@ -63,6 +67,59 @@ pub struct ArrayAppManager {
     pub(crate) apps: Vec<App>,
 }
 
+impl ArrayAppManager {
+    /// Loads the apps from `ECHOXIDE_APPS`, a JSON array of app objects, or from the file named
+    /// by `ECHOXIDE_APPS_FILE`.
+    pub fn from_env() -> Self {
+        let config = match (env::var("ECHOXIDE_APPS"), env::var("ECHOXIDE_APPS_FILE")) {
+            (Ok(apps), _) => apps,
+            (Err(_), Ok(path)) => fs::read_to_string(&path).unwrap_or_else(|e| {
+                Log::error(format!("Could not read the apps from {}: {}", path, e));
+                String::new()
+            }),
+            _ => String::new(),
+        };
+        let apps = Self::parse(&config);
+        if apps.is_empty() {
+            Log::warning("No apps are configured. Set ECHOXIDE_APPS or ECHOXIDE_APPS_FILE.");
+        }
+        ArrayAppManager { apps }
+    }
+
+    /// Parses a JSON array of apps, skipping and reporting the invalid ones.
+    fn parse(config: &str) -> Vec<App> {
+        if config.trim().is_empty() {
+            return vec![];
+        }
+        let entries: Vec<Value> = match serde_json::from_str(config) {
+            Ok(entries) => entries,
+            Err(e) => {
+                Log::error(format!("The apps configuration is not a JSON array: {}", e));
+                return vec![];
+            }
+        };
+        entries
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .as_object()
+                    .ok_or_else(|| "Every app must be a JSON object.".to_string())
+                    .and_then(App::new)
+                    .map_err(Log::error)
+                    .ok()
+            })
+            .collect()
+    }
+
+    pub fn find_by_key(&self, key: &str) -> Option<&App> {
+        self.apps.iter().find(|app| app.key == key)
+    }
+
+    pub fn find_by_id(&self, id: &str) -> Option<&App> {
+        self.apps.iter().find(|app| app.id == id)
+    }
+}
+
 pub struct CacheAppManager {
     pub(crate) enabled: bool,
     pub(crate) ttl: i64,
@@ -73,6 +130,42 @@ pub struct MySQLAppManager {
     pub(crate) version: String,
 }
 
+pub struct CacheManager {
+    pub(crate) driver: String,
+    /// Seconds a cache channel keeps its last event.
+    pub(crate) ttl: u64,
+}
+
+pub struct OutboundQueue {
+    /// Messages a connection may have waiting to be written before the policy kicks in.
+    pub(crate) max_size: usize,
+    pub(crate) when_full: SlowConsumerPolicy,
+}
+
+impl OutboundQueue {
+    /// Reads the queue size from `ECHOXIDE_OUTBOUND_QUEUE_SIZE` and the policy from
+    /// `ECHOXIDE_SLOW_CONSUMER_POLICY`, either `close` or `drop_oldest`.
+    pub fn from_env() -> Self {
+        OutboundQueue {
+            max_size: env_or("ECHOXIDE_OUTBOUND_QUEUE_SIZE", 1000),
+            // Closing makes the client reconnect and resubscribe, which beats silently missing
+            // events it can't know it lost.
+            when_full: env_or("ECHOXIDE_SLOW_CONSUMER_POLICY", SlowConsumerPolicy::Close),
+        }
+    }
+}
+
+/// Parses the environment variable `name`, falling back to `default` when it is unset or invalid.
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            Log::error(format!("Ignoring invalid {}: {}", name, value));
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
 pub struct Prometheus {
     pub(crate) prefix: String,
 }
@@ -88,7 +181,47 @@ pub struct Metrics {
 pub struct Options {
     pub(crate) adapter: Adapter,
     pub(crate) app_manager: AppManager,
+    pub(crate) cache_manager: CacheManager,
     pub(crate) debug: bool,
     pub(crate) port: u16,
     pub(crate) metrics: Metrics,
+    pub(crate) outbound_queue: OutboundQueue,
+    /// Milliseconds a connection to an app that requires user authentication has to sign in.
+    pub(crate) user_authentication_timeout: u64,
+    /// Seconds an HTTP API request's `auth_timestamp` may differ from the server clock.
+    pub(crate) auth_timestamp_window: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_apps_with_defaults_for_missing_keys() {
+        let apps = ArrayAppManager::parse(
+            r#"[{"id": "1", "key": "key-1", "secret": "secret-1", "maxConnections": 5}]"#,
+        );
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].key, "key-1");
+        assert_eq!(apps[0].max_connections, 5);
+        assert_eq!(
+            apps[0].max_event_batch_size,
+            App::default().max_event_batch_size
+        );
+    }
+
+    #[test]
+    fn skips_apps_without_credentials() {
+        let apps = ArrayAppManager::parse(
+            r#"[{"id": "1", "key": "key-1"}, "app", {"id": "2", "key": "key-2", "secret": "s"}]"#,
+        );
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].id, "2");
+    }
+
+    #[test]
+    fn rejects_configuration_that_is_not_an_array() {
+        assert!(ArrayAppManager::parse(r#"{"id": "1"}"#).is_empty());
+        assert!(ArrayAppManager::parse("").is_empty());
+    }
 }
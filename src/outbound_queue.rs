@@ -0,0 +1,248 @@
+use crate::metrics::metrics_trait::MetricsTrait;
+use crate::metrics::prometheus_metrics_driver::PrometheusMetricsDriver;
+use crate::pusher_error::PusherError;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What to do when a client reads slower than messages are queued for it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SlowConsumerPolicy {
+    /// Discard the oldest queued message to make room for the new one.
+    DropOldest,
+    /// Disconnect the client so it reconnects and resubscribes with a fresh state.
+    Close,
+}
+
+impl FromStr for SlowConsumerPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "drop_oldest" => Ok(SlowConsumerPolicy::DropOldest),
+            "close" => Ok(SlowConsumerPolicy::Close),
+            _ => Err(format!(
+                "Unknown slow consumer policy \"{}\", expected close or drop_oldest.",
+                policy
+            )),
+        }
+    }
+}
+
+/// A frame waiting to be written to the client.
+#[derive(Debug)]
+pub enum Outbound {
+    Message(String),
+    Close(u16, String),
+}
+
+struct QueueState {
+    frames: VecDeque<Outbound>,
+    /// Set once a close frame is queued or the connection is gone; nothing is accepted after.
+    closed: bool,
+}
+
+/// Bounded queue of frames for one connection, drained by its writer task. Broadcasting to a
+/// socket only ever pushes here, so a slow client never holds up the sender.
+pub struct OutboundQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+    app_id: String,
+    metrics: Option<Arc<PrometheusMetricsDriver>>,
+}
+
+impl OutboundQueue {
+    pub fn new(
+        capacity: usize,
+        policy: SlowConsumerPolicy,
+        app_id: String,
+        metrics: Option<Arc<PrometheusMetricsDriver>>,
+    ) -> Self {
+        OutboundQueue {
+            state: Mutex::new(QueueState {
+                frames: VecDeque::new(),
+                closed: false,
+            }),
+            notify: Notify::new(),
+            capacity,
+            policy,
+            app_id,
+            metrics,
+        }
+    }
+
+    /// Queues a frame, applying the slow consumer policy when the queue is full. Returns false
+    /// when the connection no longer accepts frames.
+    pub fn push(&self, frame: Outbound) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        let mut change = 1;
+        if let Outbound::Close(..) = frame {
+            state.closed = true;
+        } else if state.frames.len() >= self.capacity {
+            match self.policy {
+                SlowConsumerPolicy::DropOldest => {
+                    state.frames.pop_front();
+                    change -= 1;
+                    self.mark_dropped(1);
+                }
+                SlowConsumerPolicy::Close => {
+                    let error = PusherError::SlowConsumer;
+                    let queued = state.frames.len();
+                    state.frames.clear();
+                    state
+                        .frames
+                        .push_back(Outbound::Message(error.to_json().to_string()));
+                    state
+                        .frames
+                        .push_back(Outbound::Close(error.code(), error.to_string()));
+                    state.closed = true;
+                    // Everything queued is lost, along with the message that didn't fit.
+                    self.mark_dropped(queued + 1);
+                    self.track_depth(2 - queued as i64);
+                    self.notify.notify_one();
+                    return false;
+                }
+            }
+        }
+        state.frames.push_back(frame);
+        self.track_depth(change);
+        self.notify.notify_one();
+        true
+    }
+
+    /// Waits for the next frame. Returns `None` once the queue is shut down and drained.
+    pub async fn pop(&self) -> Option<Outbound> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(frame) = state.frames.pop_front() {
+                    self.track_depth(-1);
+                    return Some(frame);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Stops accepting frames. The writer still gets what is already queued, as long as the
+    /// connection gives it time to.
+    pub fn shutdown(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    pub fn depth(&self) -> usize {
+        self.state.lock().unwrap().frames.len()
+    }
+
+    fn track_depth(&self, change: i64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.track_outbound_queue_depth(self.app_id.clone(), change);
+        }
+    }
+
+    fn mark_dropped(&self, count: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.mark_outbound_messages_dropped(self.app_id.clone(), count);
+        }
+    }
+}
+
+impl Drop for OutboundQueue {
+    fn drop(&mut self) {
+        let depth = self.depth();
+        if depth > 0 {
+            self.track_depth(-(depth as i64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(policy: SlowConsumerPolicy) -> OutboundQueue {
+        OutboundQueue::new(2, policy, "app-id".to_string(), None)
+    }
+
+    fn message(text: &str) -> Outbound {
+        Outbound::Message(text.to_string())
+    }
+
+    async fn drain(queue: &OutboundQueue) -> Vec<String> {
+        queue.shutdown();
+        let mut frames = Vec::new();
+        while let Some(frame) = queue.pop().await {
+            frames.push(match frame {
+                Outbound::Message(text) => text,
+                Outbound::Close(code, _) => code.to_string(),
+            });
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn pops_frames_in_order() {
+        let queue = queue(SlowConsumerPolicy::Close);
+        assert!(queue.push(message("a")));
+        assert!(queue.push(message("b")));
+        assert_eq!(queue.depth(), 2);
+        assert_eq!(drain(&queue).await, ["a", "b"]);
+        assert!(!queue.push(message("c")));
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_makes_room_for_new_frames() {
+        let queue = queue(SlowConsumerPolicy::DropOldest);
+        for text in ["a", "b", "c"] {
+            assert!(queue.push(message(text)));
+        }
+        assert_eq!(queue.depth(), 2);
+        assert_eq!(drain(&queue).await, ["b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn close_replaces_the_backlog_with_an_error_and_a_close_frame() {
+        let queue = queue(SlowConsumerPolicy::Close);
+        assert!(queue.push(message("a")));
+        assert!(queue.push(message("b")));
+        assert!(!queue.push(message("c")));
+        assert!(!queue.push(message("d")));
+        let frames = drain(&queue).await;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], PusherError::SlowConsumer.to_json().to_string());
+        assert_eq!(frames[1], "4101");
+    }
+
+    #[tokio::test]
+    async fn pop_waits_for_a_frame() {
+        let queue = Arc::new(queue(SlowConsumerPolicy::Close));
+        let popped = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.pop().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(queue.push(Outbound::Close(4200, String::new())));
+        assert!(matches!(
+            popped.await.unwrap(),
+            Some(Outbound::Close(4200, _))
+        ));
+        assert!(queue.pop().await.is_none());
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!("close".parse(), Ok(SlowConsumerPolicy::Close));
+        assert_eq!("drop_oldest".parse(), Ok(SlowConsumerPolicy::DropOldest));
+        assert!("drop".parse::<SlowConsumerPolicy>().is_err());
+    }
+}
@@ -0,0 +1,559 @@
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Every message compressed with a sync flush ends with these bytes. They are stripped before
+/// sending and appended again before inflating (RFC 7692, section 7.2).
+const DEFLATE_TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Negotiated parameters of the `permessage-deflate` extension.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeflateConfig {
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+    pub server_max_window_bits: u8,
+    pub client_max_window_bits: u8,
+    /// Messages shorter than this many bytes are sent uncompressed.
+    pub threshold: usize,
+}
+
+impl DeflateConfig {
+    /// Picks the first acceptable offer of a `Sec-WebSocket-Extensions` request header.
+    pub fn negotiate(offers: &str, threshold: usize) -> Option<Self> {
+        offers
+            .split(',')
+            .find_map(|offer| Self::accept(offer, threshold))
+    }
+
+    fn accept(offer: &str, threshold: usize) -> Option<Self> {
+        let mut params = offer.split(';').map(str::trim);
+        if params.next()? != "permessage-deflate" {
+            return None;
+        }
+        let mut config = DeflateConfig {
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+            server_max_window_bits: 15,
+            client_max_window_bits: 15,
+            threshold,
+        };
+        let mut seen = Vec::new();
+        for param in params {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+            // An offer repeating a parameter has to be declined.
+            if seen.contains(&name) {
+                return None;
+            }
+            seen.push(name);
+            match (name, value) {
+                ("server_no_context_takeover", None) => config.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => config.client_no_context_takeover = true,
+                ("server_max_window_bits", Some(bits)) => {
+                    config.server_max_window_bits = Self::window_bits(bits)?
+                }
+                ("client_max_window_bits", None) => {}
+                ("client_max_window_bits", Some(bits)) => {
+                    config.client_max_window_bits = Self::window_bits(bits)?
+                }
+                _ => return None,
+            }
+        }
+        // zlib cannot produce raw deflate streams limited to a 256 byte window.
+        if config.server_max_window_bits < 9 {
+            return None;
+        }
+        Some(config)
+    }
+
+    fn window_bits(value: &str) -> Option<u8> {
+        value
+            .parse::<u8>()
+            .ok()
+            .filter(|bits| (8..=15).contains(bits))
+    }
+
+    /// The `Sec-WebSocket-Extensions` response header accepting the offer.
+    pub fn response(&self) -> String {
+        let mut response = String::from("permessage-deflate");
+        if self.server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            response.push_str("; client_no_context_takeover");
+        }
+        if self.server_max_window_bits < 15 {
+            response.push_str(&format!(
+                "; server_max_window_bits={}",
+                self.server_max_window_bits
+            ));
+        }
+        if self.client_max_window_bits < 15 {
+            response.push_str(&format!(
+                "; client_max_window_bits={}",
+                self.client_max_window_bits
+            ));
+        }
+        response
+    }
+}
+
+/// Compresses messages sent to the client.
+pub struct Deflater {
+    compress: Compress,
+    no_context_takeover: bool,
+    threshold: usize,
+}
+
+impl Deflater {
+    pub fn new(config: &DeflateConfig) -> Self {
+        Deflater {
+            compress: Compress::new_with_window_bits(
+                Compression::default(),
+                false,
+                config.server_max_window_bits,
+            ),
+            no_context_takeover: config.server_no_context_takeover,
+            threshold: config.threshold,
+        }
+    }
+
+    /// Compresses a message payload, or returns `None` when it is below the threshold.
+    pub fn compress(&mut self, payload: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        if payload.len() < self.threshold {
+            return Ok(None);
+        }
+        let mut output = Vec::with_capacity(payload.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&payload[consumed..], &mut output, FlushCompress::Sync)
+                .map_err(io::Error::other)?;
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == payload.len() && output.len() < output.capacity() {
+                break;
+            }
+            output.reserve(output.capacity().max(1024));
+        }
+        if output.ends_with(&DEFLATE_TRAILER) {
+            output.truncate(output.len() - DEFLATE_TRAILER.len());
+        }
+        if self.no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(Some(output))
+    }
+}
+
+struct Inflater {
+    decompress: Decompress,
+    no_context_takeover: bool,
+}
+
+impl Inflater {
+    fn inflate(&mut self, mut payload: Vec<u8>, max_size: usize) -> io::Result<Vec<u8>> {
+        payload.extend_from_slice(&DEFLATE_TRAILER);
+        let mut output = Vec::with_capacity(payload.len() * 2);
+        let start = self.decompress.total_in();
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            self.decompress
+                .decompress_vec(&payload[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if output.len() > max_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Inflated message is too large",
+                ));
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if consumed == payload.len() && output.len() < output.capacity() {
+                break;
+            }
+            output.reserve(output.capacity().max(1024));
+        }
+        if self.no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(output)
+    }
+}
+
+/// Sits between the connection and the frame parser, which does not know about extensions.
+/// Compressed messages are inflated and handed on as plain single frames; every other frame
+/// passes through untouched.
+pub struct InflateReader<R> {
+    inner: R,
+    inflater: Inflater,
+    max_message_size: usize,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_position: usize,
+    /// Opcode and payload of a compressed message whose final fragment hasn't arrived yet.
+    message: Option<(u8, Vec<u8>)>,
+}
+
+impl<R> InflateReader<R> {
+    pub fn new(inner: R, config: &DeflateConfig, max_message_size: usize) -> Self {
+        InflateReader {
+            inner,
+            inflater: Inflater {
+                decompress: Decompress::new(false),
+                no_context_takeover: config.client_no_context_takeover,
+            },
+            max_message_size,
+            input: Vec::new(),
+            output: Vec::new(),
+            output_position: 0,
+            message: None,
+        }
+    }
+
+    /// Moves every complete frame from `input` to `output`, inflating compressed messages.
+    fn process(&mut self) -> io::Result<()> {
+        while let Some(header) = FrameHeader::parse(&self.input) {
+            if header.payload_len > self.max_message_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Frame is too large",
+                ));
+            }
+            let frame_len = header.len + header.payload_len;
+            if self.input.len() < frame_len {
+                break;
+            }
+            let compressed_start = header.rsv1 && (header.opcode == 0x1 || header.opcode == 0x2);
+            let compressed_continuation = header.opcode == 0x0 && self.message.is_some();
+            if !compressed_start && !compressed_continuation {
+                self.output.extend_from_slice(&self.input[..frame_len]);
+                self.input.drain(..frame_len);
+                continue;
+            }
+            let mut payload: Vec<u8> = self.input[header.len..frame_len].to_vec();
+            self.input.drain(..frame_len);
+            if let Some(mask) = header.mask {
+                for (i, byte) in payload.iter_mut().enumerate() {
+                    *byte ^= mask[i % 4];
+                }
+            }
+            let (opcode, mut message) = match self.message.take() {
+                Some((opcode, mut message)) => {
+                    message.extend_from_slice(&payload);
+                    (opcode, message)
+                }
+                None => (header.opcode, payload),
+            };
+            if message.len() > self.max_message_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Message is too large",
+                ));
+            }
+            if !header.fin {
+                self.message = Some((opcode, message));
+                continue;
+            }
+            message = self.inflater.inflate(message, self.max_message_size)?;
+            FrameHeader::write(&mut self.output, opcode, false, message.len());
+            self.output.extend_from_slice(&message);
+        }
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for InflateReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.output_position < this.output.len() {
+                let pending = &this.output[this.output_position..];
+                let len = pending.len().min(buf.remaining());
+                buf.put_slice(&pending[..len]);
+                this.output_position += len;
+                if this.output_position == this.output.len() {
+                    this.output.clear();
+                    this.output_position = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            let mut chunk = [0u8; 8192];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.input.extend_from_slice(read.filled());
+            this.process()?;
+        }
+    }
+}
+
+pub(crate) struct FrameHeader {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    /// Length of the header itself.
+    len: usize,
+    payload_len: usize,
+}
+
+impl FrameHeader {
+    /// Parses the header at the start of `buffer`, or returns `None` until it is complete.
+    fn parse(buffer: &[u8]) -> Option<Self> {
+        let (b0, b1) = (*buffer.first()?, *buffer.get(1)?);
+        let masked = b1 & 0x80 != 0;
+        let (payload_len, mut len) = match b1 & 0x7f {
+            126 => (
+                u16::from_be_bytes(buffer.get(2..4)?.try_into().ok()?) as usize,
+                4,
+            ),
+            127 => (
+                u64::from_be_bytes(buffer.get(2..10)?.try_into().ok()?) as usize,
+                10,
+            ),
+            payload_len => (payload_len as usize, 2),
+        };
+        let mask = if masked {
+            let mask = buffer.get(len..len + 4)?.try_into().ok()?;
+            len += 4;
+            Some(mask)
+        } else {
+            None
+        };
+        Some(FrameHeader {
+            fin: b0 & 0x80 != 0,
+            rsv1: b0 & 0x40 != 0,
+            opcode: b0 & 0x0f,
+            mask,
+            len,
+            payload_len,
+        })
+    }
+
+    /// Writes the header of an unmasked, final frame.
+    pub(crate) fn write(buffer: &mut Vec<u8>, opcode: u8, rsv1: bool, payload_len: usize) {
+        buffer.push(0x80 | (rsv1 as u8) << 6 | opcode);
+        if payload_len < 126 {
+            buffer.push(payload_len as u8);
+        } else if payload_len < 65536 {
+            buffer.push(126);
+            buffer.extend_from_slice(&(payload_len as u16).to_be_bytes());
+        } else {
+            buffer.push(127);
+            buffer.extend_from_slice(&(payload_len as u64).to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    fn config(no_context_takeover: bool) -> DeflateConfig {
+        DeflateConfig {
+            server_no_context_takeover: no_context_takeover,
+            client_no_context_takeover: no_context_takeover,
+            server_max_window_bits: 15,
+            client_max_window_bits: 15,
+            threshold: 0,
+        }
+    }
+
+    /// A masked frame as a client would send it.
+    fn client_frame(opcode: u8, rsv1: bool, fin: bool, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        FrameHeader::write(&mut frame, opcode, rsv1, payload.len());
+        if !fin {
+            frame[0] &= 0x7f;
+        }
+        frame[1] |= 0x80;
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    async fn read_all(input: &[u8], config: &DeflateConfig, max: usize) -> io::Result<Vec<u8>> {
+        let mut reader = InflateReader::new(input, config, max);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).await?;
+        Ok(output)
+    }
+
+    /// Opcodes and payloads of the unmasked frames handed to the frame parser.
+    fn frames(mut output: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while let Some(header) = FrameHeader::parse(output) {
+            assert!(header.fin && !header.rsv1 && header.mask.is_none());
+            let end = header.len + header.payload_len;
+            frames.push((header.opcode, output[header.len..end].to_vec()));
+            output = &output[end..];
+        }
+        assert!(output.is_empty());
+        frames
+    }
+
+    #[test]
+    fn negotiate_accepts_the_first_acceptable_offer() {
+        let config = DeflateConfig::negotiate(
+            "x-webkit-deflate-frame, permessage-deflate; server_max_window_bits=8, \
+             permessage-deflate; client_max_window_bits; server_no_context_takeover",
+            10,
+        )
+        .unwrap();
+        assert!(config.server_no_context_takeover);
+        assert!(!config.client_no_context_takeover);
+        assert_eq!(config.server_max_window_bits, 15);
+        assert_eq!(config.threshold, 10);
+        assert_eq!(
+            config.response(),
+            "permessage-deflate; server_no_context_takeover"
+        );
+    }
+
+    #[test]
+    fn negotiate_reads_window_bits() {
+        let config = DeflateConfig::negotiate(
+            "permessage-deflate; server_max_window_bits=\"10\"; client_max_window_bits=12",
+            0,
+        )
+        .unwrap();
+        assert_eq!(config.server_max_window_bits, 10);
+        assert_eq!(config.client_max_window_bits, 12);
+        assert_eq!(
+            config.response(),
+            "permessage-deflate; server_max_window_bits=10; client_max_window_bits=12"
+        );
+    }
+
+    #[test]
+    fn negotiate_declines_invalid_offers() {
+        for offer in [
+            "permessage-deflate; server_no_context_takeover; server_no_context_takeover",
+            "permessage-deflate; server_max_window_bits=16",
+            "permessage-deflate; server_max_window_bits",
+            "permessage-deflate; server_no_context_takeover=1",
+            "permessage-deflate; unknown",
+            "deflate-frame",
+        ] {
+            assert_eq!(DeflateConfig::negotiate(offer, 0), None, "{}", offer);
+        }
+    }
+
+    #[test]
+    fn frame_header_round_trips_every_length_encoding() {
+        for payload_len in [0, 125, 126, 65535, 65536] {
+            let mut buffer = Vec::new();
+            FrameHeader::write(&mut buffer, 0x1, true, payload_len);
+            let header = FrameHeader::parse(&buffer).unwrap();
+            assert!(header.fin && header.rsv1);
+            assert_eq!(header.opcode, 0x1);
+            assert_eq!(header.mask, None);
+            assert_eq!(header.len, buffer.len());
+            assert_eq!(header.payload_len, payload_len);
+        }
+    }
+
+    #[test]
+    fn frame_header_waits_for_the_whole_header() {
+        let frame = client_frame(0x1, false, false, &[0; 300]);
+        assert!(FrameHeader::parse(&frame[..1]).is_none());
+        assert!(FrameHeader::parse(&frame[..3]).is_none());
+        assert!(FrameHeader::parse(&frame[..7]).is_none());
+        let header = FrameHeader::parse(&frame[..8]).unwrap();
+        assert!(!header.fin);
+        assert_eq!(header.mask, Some([0x12, 0x34, 0x56, 0x78]));
+        assert_eq!(header.len, 8);
+        assert_eq!(header.payload_len, 300);
+    }
+
+    #[tokio::test]
+    async fn inflates_compressed_messages_and_passes_others_through() {
+        let config = config(false);
+        let compressed = Deflater::new(&config).compress(b"hello").unwrap().unwrap();
+        let mut input = client_frame(0x1, true, true, &compressed);
+        input.extend(client_frame(0x9, false, true, b"ping"));
+        let output = read_all(&input, &config, 1024).await.unwrap();
+        let ping = client_frame(0x9, false, true, b"ping");
+        assert!(output.ends_with(&ping));
+        assert_eq!(
+            frames(&output[..output.len() - ping.len()]),
+            [(0x1, b"hello".to_vec())]
+        );
+    }
+
+    #[tokio::test]
+    async fn inflates_fragmented_messages() {
+        let config = config(false);
+        let message = "fragmented ".repeat(20);
+        let compressed = Deflater::new(&config)
+            .compress(message.as_bytes())
+            .unwrap()
+            .unwrap();
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        let mut input = client_frame(0x1, true, false, first);
+        input.extend(client_frame(0x0, false, true, second));
+        let output = read_all(&input, &config, 1024).await.unwrap();
+        assert_eq!(frames(&output), [(0x1, message.into_bytes())]);
+    }
+
+    #[tokio::test]
+    async fn rejects_messages_inflating_past_the_limit() {
+        let config = config(false);
+        let compressed = Deflater::new(&config)
+            .compress(&[b'a'; 10_000])
+            .unwrap()
+            .unwrap();
+        assert!(compressed.len() < 1000);
+        let input = client_frame(0x1, true, true, &compressed);
+        let error = read_all(&input, &config, 1000).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn keeps_or_resets_the_window_between_messages() {
+        for no_context_takeover in [false, true] {
+            let config = config(no_context_takeover);
+            let mut deflater = Deflater::new(&config);
+            let first = deflater.compress(b"repeated message").unwrap().unwrap();
+            let second = deflater.compress(b"repeated message").unwrap().unwrap();
+            // A reset window compresses the same message the same way every time.
+            assert_eq!(first == second, no_context_takeover);
+            let mut input = client_frame(0x1, true, true, &first);
+            input.extend(client_frame(0x1, true, true, &second));
+            let output = read_all(&input, &config, 1024).await.unwrap();
+            assert_eq!(
+                frames(&output),
+                [
+                    (0x1, b"repeated message".to_vec()),
+                    (0x1, b"repeated message".to_vec())
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn leaves_short_messages_uncompressed() {
+        let mut config = config(false);
+        config.threshold = 10;
+        let mut deflater = Deflater::new(&config);
+        assert_eq!(deflater.compress(b"short").unwrap(), None);
+        assert!(deflater.compress(b"long enough").unwrap().is_some());
+    }
+}
@@ -0,0 +1,77 @@
+use crate::pusher_error::PusherError;
+
+/// Details of the Pusher protocol that differ between client generations.
+#[derive(Debug, PartialEq)]
+pub struct Protocol {
+    pub version: u8,
+    /// Whether `pusher:connection_established` advertises the activity timeout.
+    pub sends_activity_timeout: bool,
+    /// Whether the client may sign in with `pusher:signin`.
+    pub supports_user_authentication: bool,
+}
+
+/// Every protocol version the server speaks, oldest first.
+pub const PROTOCOLS: &[Protocol] = &[
+    Protocol {
+        version: 5,
+        sends_activity_timeout: false,
+        supports_user_authentication: false,
+    },
+    Protocol {
+        version: 6,
+        sends_activity_timeout: false,
+        supports_user_authentication: false,
+    },
+    Protocol {
+        version: 7,
+        sends_activity_timeout: true,
+        supports_user_authentication: true,
+    },
+];
+
+impl Protocol {
+    /// Picks the behavior for the `protocol` query parameter of a connection.
+    pub fn negotiate(protocol: Option<&str>) -> Result<&'static Protocol, PusherError> {
+        let version = protocol
+            .ok_or(PusherError::NoProtocolVersion)?
+            .parse::<u8>()
+            .map_err(|_| PusherError::InvalidVersionStringFormat)?;
+        PROTOCOLS
+            .iter()
+            .find(|protocol| protocol.version == version)
+            .ok_or(PusherError::UnsupportedProtocolVersion)
+    }
+
+    pub fn latest() -> &'static Protocol {
+        PROTOCOLS.last().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_supported_versions() {
+        assert_eq!(Protocol::negotiate(Some("5")).unwrap().version, 5);
+        let protocol = Protocol::negotiate(Some("7")).unwrap();
+        assert!(protocol.sends_activity_timeout && protocol.supports_user_authentication);
+        assert_eq!(protocol, Protocol::latest());
+    }
+
+    #[test]
+    fn rejects_missing_malformed_and_unknown_versions() {
+        assert_eq!(
+            Protocol::negotiate(None),
+            Err(PusherError::NoProtocolVersion)
+        );
+        assert_eq!(
+            Protocol::negotiate(Some("7.0")),
+            Err(PusherError::InvalidVersionStringFormat)
+        );
+        assert_eq!(
+            Protocol::negotiate(Some("4")),
+            Err(PusherError::UnsupportedProtocolVersion)
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Errors reported to clients with the codes of the Pusher protocol.
+///
+/// Codes 4000-4099 close the connection for good, 4100-4199 ask the client to reconnect after
+/// backing off, 4200-4299 ask it to reconnect immediately and 4300+ leave it open.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PusherError {
+    AppNotFound,
+    AppDisabled,
+    InvalidVersionStringFormat,
+    UnsupportedProtocolVersion,
+    NoProtocolVersion,
+    Unauthorized,
+    AuthenticationTimeout,
+    OverCapacity,
+    SlowConsumer,
+    ServerClosing,
+    PongNotReceived,
+    InvalidMessage(String),
+    ClientEventRejected(String),
+}
+
+impl PusherError {
+    pub fn code(&self) -> u16 {
+        match self {
+            PusherError::AppNotFound => 4001,
+            PusherError::AppDisabled => 4003,
+            PusherError::InvalidVersionStringFormat => 4006,
+            PusherError::UnsupportedProtocolVersion => 4007,
+            PusherError::NoProtocolVersion => 4008,
+            PusherError::Unauthorized => 4009,
+            PusherError::AuthenticationTimeout => 4009,
+            PusherError::OverCapacity => 4100,
+            PusherError::SlowConsumer => 4101,
+            PusherError::ServerClosing => 4200,
+            PusherError::PongNotReceived => 4201,
+            PusherError::InvalidMessage(_) => 4300,
+            PusherError::ClientEventRejected(_) => 4301,
+        }
+    }
+
+    pub fn closes_connection(&self) -> bool {
+        self.code() < 4300
+    }
+
+    /// The `pusher:error` frame sent to the client.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "event": "pusher:error",
+            "data": {
+                "code": self.code(),
+                "message": self.to_string(),
+            },
+        })
+    }
+}
+
+impl fmt::Display for PusherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PusherError::AppNotFound => write!(f, "App key does not exist."),
+            PusherError::AppDisabled => write!(f, "The app is not enabled."),
+            PusherError::InvalidVersionStringFormat => write!(f, "Invalid version string format."),
+            PusherError::UnsupportedProtocolVersion => write!(f, "Unsupported protocol version."),
+            PusherError::NoProtocolVersion => write!(f, "No protocol version supplied."),
+            PusherError::Unauthorized => write!(f, "The connection is unauthorized."),
+            PusherError::AuthenticationTimeout => {
+                write!(f, "Connection not authorized within timeout.")
+            }
+            PusherError::OverCapacity => write!(f, "Over capacity."),
+            PusherError::SlowConsumer => write!(f, "Client is too slow to consume messages."),
+            PusherError::ServerClosing => {
+                write!(f, "Server is closing. Please reconnect shortly.")
+            }
+            PusherError::PongNotReceived => write!(f, "Pong reply not received in time."),
+            PusherError::InvalidMessage(message) => write!(f, "{}", message),
+            PusherError::ClientEventRejected(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for PusherError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_follow_the_pusher_ranges() {
+        assert_eq!(PusherError::AppNotFound.code(), 4001);
+        assert_eq!(PusherError::Unauthorized.code(), 4009);
+        assert_eq!(PusherError::OverCapacity.code(), 4100);
+        assert_eq!(PusherError::PongNotReceived.code(), 4201);
+        assert_eq!(PusherError::InvalidMessage(String::new()).code(), 4300);
+        assert_eq!(PusherError::ClientEventRejected(String::new()).code(), 4301);
+    }
+
+    #[test]
+    fn only_codes_below_4300_close_the_connection() {
+        assert!(PusherError::AppDisabled.closes_connection());
+        assert!(PusherError::ServerClosing.closes_connection());
+        assert!(!PusherError::InvalidMessage(String::new()).closes_connection());
+    }
+
+    #[test]
+    fn serializes_as_a_pusher_error_event() {
+        assert_eq!(
+            PusherError::InvalidMessage("Bad.".to_string()).to_json(),
+            json!({
+                "event": "pusher:error",
+                "data": { "code": 4300, "message": "Bad." },
+            })
+        );
+    }
+}
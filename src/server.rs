@@ -1,11 +1,18 @@
-use crate::http_handler::{HttpHandler, PrometheusQuery};
+use crate::adapters::local_adapter::LocalAdapter;
+use crate::app::App;
+use crate::cache_managers::cache_manager::CacheManagerInterface;
+use crate::cache_managers::memory_cache_manager::MemoryCacheManager;
+use crate::http_handler::{ChannelsQuery, HttpHandler, PrometheusQuery};
 use crate::log::Log;
 // use crate::metrics::prometheus_metrics_driver::PrometheusMetricsDriver;
 use crate::options::{
-    Adapter, AppManager, ArrayAppManager, CacheAppManager, ClusterAdapter, Metrics,
-    MySQLAppManager, NatsAdapter, Options, Prometheus, RedisAdapter,
+    Adapter, AppManager, ArrayAppManager, CacheAppManager, CacheManager, ClusterAdapter, Metrics,
+    MySQLAppManager, NatsAdapter, Options, OutboundQueue as OutboundQueueOptions, Prometheus,
+    RedisAdapter,
 };
-use crate::ws_handler::WSHandler;
+use crate::outbound_queue::{OutboundQueue, SlowConsumerPolicy};
+use crate::web_socket::WebSocketUpgrade;
+use crate::ws_handler::{PusherWebsocketQuery, WSHandler};
 use tracing_subscriber;
 
 use axum::routing::{get, post, Route};
@@ -16,13 +23,16 @@ use axum::{
 };
 use axum::{Router, ServiceExt};
 
-use crate::message::PusherApiMessage;
+use crate::message::{PusherApiMessage, PusherBatchMessage};
 use crate::metrics::prometheus_metrics_driver::PrometheusMetricsDriver;
-use axum::extract::Query;
+use axum::extract::{ConnectInfo, Path, Query, Request};
 use axum::handler::HandlerWithoutStateExt;
+use axum::middleware::{self, Next};
+use std::collections::HashMap;
 use std::fmt::format;
 use std::net::SocketAddr;
 use std::sync::{Arc, Weak};
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 use tokio::{join, signal};
@@ -38,6 +48,8 @@ pub struct Server {
     ws_handler: Mutex<Option<Arc<WSHandler>>>,
     pub(crate) metrics: Mutex<Option<Arc<PrometheusMetricsDriver>>>,
     http_handler: Mutex<Option<Arc<HttpHandler>>>,
+    pub(crate) adapter: Mutex<LocalAdapter>,
+    pub(crate) cache_manager: Mutex<Box<dyn CacheManagerInterface + Send>>,
 }
 
 impl Server {
@@ -66,7 +78,7 @@ impl Server {
             },
             app_manager: AppManager {
                 driver: "".to_string(),
-                array: ArrayAppManager { apps: vec![] },
+                array: ArrayAppManager::from_env(),
                 cache: CacheAppManager {
                     enabled: false,
                     ttl: 0,
@@ -76,6 +88,10 @@ impl Server {
                     version: "".to_string(),
                 },
             },
+            cache_manager: CacheManager {
+                driver: String::from("memory"),
+                ttl: 3600,
+            },
             debug: true,
             port: 6001,
             metrics: Metrics {
@@ -87,13 +103,19 @@ impl Server {
                 },
                 port: 9601,
             },
+            outbound_queue: OutboundQueueOptions::from_env(),
+            user_authentication_timeout: 3000,
+            auth_timestamp_window: 600,
         };
+        let cache_manager = Self::create_cache_manager(&options.cache_manager);
         let server = Arc::new(Server {
             closing: false,
             options: Some(options),
             ws_handler: Mutex::new(None),
             metrics: Mutex::new(None),
             http_handler: Mutex::new(None),
+            adapter: Mutex::new(LocalAdapter::new()),
+            cache_manager: Mutex::new(cache_manager),
         });
         let ws_handler = Arc::new(WSHandler {
             server: Arc::downgrade(&server), // Create a Weak reference from the server
@@ -149,6 +171,109 @@ impl Server {
             _ = terminate => {},
         }
     }
+    pub(crate) fn find_app_by_key(&self, key: &str) -> Option<App> {
+        self.options
+            .as_ref()?
+            .app_manager
+            .array
+            .find_by_key(key)
+            .cloned()
+    }
+
+    pub(crate) fn find_app_by_id(&self, id: &str) -> Option<App> {
+        self.options
+            .as_ref()?
+            .app_manager
+            .array
+            .find_by_id(id)
+            .cloned()
+    }
+
+    /// Picks the cache manager named by the `cache_manager.driver` option.
+    fn create_cache_manager(options: &CacheManager) -> Box<dyn CacheManagerInterface + Send> {
+        match options.driver.as_str() {
+            "memory" => Box::new(MemoryCacheManager::new()),
+            driver => {
+                Log::warning(&format!(
+                    "Unknown cache manager driver \"{}\", falling back to memory.",
+                    driver
+                ));
+                Box::new(MemoryCacheManager::new())
+            }
+        }
+    }
+
+    /// Remembers the last event sent to a cache channel so later subscribers can replay it.
+    pub(crate) async fn cache_event(&self, app_id: &str, channel: &str, event: &serde_json::Value) {
+        let ttl = self
+            .options
+            .as_ref()
+            .map_or(3600, |options| options.cache_manager.ttl);
+        self.cache_manager
+            .lock()
+            .await
+            .set(&Self::cache_key(app_id, channel), &event.to_string(), ttl)
+            .await;
+    }
+
+    pub(crate) async fn cached_event(
+        &self,
+        app_id: &str,
+        channel: &str,
+    ) -> Option<serde_json::Value> {
+        let cached = self
+            .cache_manager
+            .lock()
+            .await
+            .get(&Self::cache_key(app_id, channel))
+            .await?;
+        serde_json::from_str(&cached).ok()
+    }
+
+    /// Seconds before the cached event of a channel expires.
+    pub(crate) async fn cached_event_ttl(&self, app_id: &str, channel: &str) -> Option<u64> {
+        self.cache_manager
+            .lock()
+            .await
+            .ttl(&Self::cache_key(app_id, channel))
+            .await
+    }
+
+    fn cache_key(app_id: &str, channel: &str) -> String {
+        format!("app:{}:channel:{}:cache_miss", app_id, channel)
+    }
+
+    pub(crate) fn user_authentication_timeout(&self) -> Duration {
+        let millis = self
+            .options
+            .as_ref()
+            .map_or(3000, |options| options.user_authentication_timeout);
+        Duration::from_millis(millis)
+    }
+
+    pub(crate) fn auth_timestamp_window(&self) -> Duration {
+        let seconds = self
+            .options
+            .as_ref()
+            .map_or(600, |options| options.auth_timestamp_window);
+        Duration::from_secs(seconds)
+    }
+
+    /// Creates the queue holding the frames waiting to be written to one connection.
+    pub(crate) async fn outbound_queue(&self, app_id: String) -> OutboundQueue {
+        let (max_size, when_full) =
+            self.options
+                .as_ref()
+                .map_or((1000, SlowConsumerPolicy::Close), |options| {
+                    (
+                        options.outbound_queue.max_size,
+                        options.outbound_queue.when_full,
+                    )
+                });
+        let metrics = self.metrics.lock().await.clone();
+        OutboundQueue::new(max_size, when_full, app_id, metrics)
+    }
+
     pub fn get_instance(self) -> Self {
         self
     }
@@ -186,21 +311,78 @@ impl Server {
             .with(tracing_subscriber::fmt::layer())
             .init();
         let http_handler = self.http_handler.lock().await.clone().unwrap(); // Clone the Arc
-        let router = Router::new()
-            .route("/app/:app_id", get(WSHandler::ws_handler))
-            .route("/health", get(HttpHandler::health_check))
+        let ws_handler = self.ws_handler.lock().await.clone().unwrap();
+        let events_handler = http_handler.clone();
+        let batch_events_handler = http_handler.clone();
+        let user_events_handler = http_handler.clone();
+        let channel_handler = http_handler.clone();
+        let channels_handler = http_handler.clone();
+        let auth_handler = http_handler.clone();
+        let api = Router::new()
             .route(
                 "/apps/:app_id/channels/:channel_name",
-                get(HttpHandler::channel),
+                get(move |path: Path<(String, String)>| {
+                    let http_handler = channel_handler.clone();
+                    async move { http_handler.channel(path).await }
+                }),
             )
-            .route("/apps/:app_id/channels", get(HttpHandler::channels))
-            .route("/ready", get(HttpHandler::ready))
             .route(
-                "/metrics",
-                get(move |message: Json<PusherApiMessage>| async move {
-                    http_handler.clone().events(message).await
+                "/apps/:app_id/channels",
+                get(move |path: Path<String>, query: Query<ChannelsQuery>| {
+                    let http_handler = channels_handler.clone();
+                    async move { http_handler.channels(path, query).await }
+                }),
+            )
+            .route(
+                "/apps/:app_id/events",
+                post(move |path: Path<String>, message: Json<PusherApiMessage>| {
+                    let http_handler = events_handler.clone();
+                    async move { http_handler.events(path, message).await }
                 }),
             )
+            .route(
+                "/apps/:app_id/batch_events",
+                post(
+                    move |path: Path<String>, message: Json<PusherBatchMessage>| {
+                        let http_handler = batch_events_handler.clone();
+                        async move { http_handler.batch_events(path, message).await }
+                    },
+                ),
+            )
+            .route(
+                "/apps/:app_id/users/:user_id/events",
+                post(
+                    move |path: Path<(String, String)>, message: Json<PusherApiMessage>| {
+                        let http_handler = user_events_handler.clone();
+                        async move { http_handler.user_events(path, message).await }
+                    },
+                ),
+            )
+            .route_layer(middleware::from_fn(
+                move |path: Path<HashMap<String, String>>,
+                      query: Query<HashMap<String, String>>,
+                      request: Request,
+                      next: Next| {
+                    let http_handler = auth_handler.clone();
+                    async move { http_handler.authenticate(path, query, request, next).await }
+                },
+            ));
+        let router = Router::new()
+            .route(
+                "/app/:app_id",
+                get(
+                    move |path: Path<String>,
+                          query: Query<PusherWebsocketQuery>,
+                          ws: WebSocketUpgrade,
+                          connect_info: ConnectInfo<SocketAddr>| {
+                        let ws_handler = ws_handler.clone();
+                        async move { ws_handler.ws_handler(path, query, ws, connect_info).await }
+                    },
+                ),
+            )
+            .route("/health", get(HttpHandler::health_check))
+            .merge(api)
+            .route("/ready", get(HttpHandler::ready))
             .layer(
                 TraceLayer::new_for_http()
                     .make_span_with(DefaultMakeSpan::default().include_headers(true)),
@@ -3,7 +3,7 @@ use hex::encode as hex_encode;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 
-struct Token {
+pub(crate) struct Token {
     key: String,
     secret: String,
 }
@@ -38,6 +38,12 @@ impl Utils {
         })
     }
 
+    /// Matches the whole of `value` against a pattern where `*` stands for any run of characters.
+    pub fn wildcard_match(pattern: &str, value: &str) -> bool {
+        let regex = format!("^{}$", regex::escape(pattern).replace(r"\*", ".*"));
+        Regex::new(&regex).is_ok_and(|regex| regex.is_match(value))
+    }
+
     pub fn is_private_channel(channel: &str) -> bool {
         Self::is_match(Self::PRIVATE_CHANNEL_PATTERNS, channel)
     }
@@ -54,6 +60,13 @@ impl Utils {
         Self::is_match(Self::CACHING_CHANNEL_PATTERNS, channel)
     }
 
+    /// The user id addressed by a `#server-to-user-{id}` channel.
+    pub fn server_to_user_id(channel: &str) -> Option<&str> {
+        channel
+            .strip_prefix("#server-to-user-")
+            .filter(|user_id| !user_id.is_empty())
+    }
+
     pub fn is_client_event(event: &str) -> bool {
         Self::is_match(Self::CLIENT_EVENT_PATTERNS, event)
     }
@@ -63,7 +76,7 @@ impl Utils {
             .unwrap()
             .is_match(name)
     }
-    fn to_ordered_array(map: &HashMap<&str, &str>) -> Vec<String> {
+    pub(crate) fn to_ordered_array(map: &HashMap<&str, &str>) -> Vec<String> {
         let mut pairs: Vec<_> = map.iter().collect();
         pairs.sort_by(|a, b| a.0.cmp(b.0));
         pairs.iter().map(|(k, v)| format!("{}={}", k, v)).collect()
@@ -91,3 +104,36 @@ impl Utils {
         channel.starts_with("private-encrypted-")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_match_covers_the_whole_value() {
+        assert!(Utils::wildcard_match(
+            "*.example.com",
+            "https://app.example.com"
+        ));
+        assert!(Utils::wildcard_match(
+            "https://example.com",
+            "https://example.com"
+        ));
+        assert!(Utils::wildcard_match("*", ""));
+        assert!(!Utils::wildcard_match(
+            "https://example.com",
+            "https://example.com.evil"
+        ));
+        assert!(!Utils::wildcard_match(
+            "*.example.com",
+            "https://example.company"
+        ));
+    }
+
+    #[test]
+    fn wildcard_match_treats_other_characters_literally() {
+        assert!(Utils::wildcard_match("app.(test)", "app.(test)"));
+        assert!(!Utils::wildcard_match("app.test", "appxtest"));
+        assert!(!Utils::wildcard_match("[a-z]", "a"));
+    }
+}
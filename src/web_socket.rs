@@ -1,55 +1,303 @@
-mod app;
-mod options;
-
+use crate::permessage_deflate::{DeflateConfig, Deflater, FrameHeader, InflateReader};
 use axum::{
     async_trait,
     extract::FromRequestParts,
     http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
-    response::Response,
-    Error,
+    response::{IntoResponse, Response},
+};
+use fastwebsockets::{
+    FragmentCollectorRead, Frame, OpCode, Payload, Role, WebSocket, WebSocketError, WebSocketWrite,
 };
-use futures_util::{Sink, Stream};
 use hyper::upgrade::Upgraded;
 use hyper_util::rt::TokioIo;
 use std::future::Future;
-use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::Arc;
+use tokio::io::{AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::Mutex;
+
+/// Largest message, after reassembling fragments, accepted from a client.
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+type Io = TokioIo<Upgraded>;
+
+/// A complete message read from the client. Pings are answered and close frames echoed by the
+/// socket before they get here; pings are still reported so they count as client activity.
+pub enum Event {
+    Text(Payload<'static>),
+    Binary,
+    Ping,
+    Pong,
+    Close { code: u16, reason: String },
+}
+
+/// Server side of an upgraded connection. Reads go through `recv`, writes through the
+/// `WSWriter` handles, which can be moved to other tasks.
+pub struct WS {
+    read: Reader,
+    writer: WSWriter,
+}
+
+/// The frames of a connection that negotiated permessage-deflate are inflated before the
+/// frame parser sees them; every other connection is read by fastwebsockets directly.
+enum Reader {
+    Plain(FragmentCollectorRead<ReadHalf<Io>>),
+    Deflate(Box<FragmentCollectorRead<InflateReader<ReadHalf<Io>>>>),
+}
 
-struct WS(pub(crate) web_socket::WebSocket<TokioIo<Upgraded>>);
+impl WS {
+    fn server(io: Io, deflate: Option<DeflateConfig>) -> Self {
+        let Some(deflate) = deflate else {
+            let mut ws = WebSocket::after_handshake(io, Role::Server);
+            ws.set_max_message_size(MAX_MESSAGE_SIZE);
+            // Pings are answered in `recv`, which also reports them as activity.
+            ws.set_auto_pong(false);
+            let (read, write) = ws.split(tokio::io::split);
+            return WS {
+                read: Reader::Plain(FragmentCollectorRead::new(read)),
+                writer: WSWriter(Arc::new(Mutex::new(Writer::Plain(write)))),
+            };
+        };
+        let (read, write) = tokio::io::split(io);
+        let read = InflateReader::new(read, &deflate, MAX_MESSAGE_SIZE);
+        // Only the read half of fastwebsockets is used, writes go through `DeflateWriter` so
+        // they can be compressed. The sink stands in for the write half it expects.
+        let mut ws =
+            WebSocket::after_handshake(tokio::io::join(read, tokio::io::sink()), Role::Server);
+        ws.set_max_message_size(MAX_MESSAGE_SIZE);
+        ws.set_auto_pong(false);
+        let (read, _) = ws.split(|io| io.into_inner());
+        WS {
+            read: Reader::Deflate(Box::new(FragmentCollectorRead::new(read))),
+            writer: WSWriter(Arc::new(Mutex::new(Writer::Deflate(DeflateWriter {
+                stream: write,
+                deflater: Deflater::new(&deflate),
+                closed: false,
+                buffer: Vec::new(),
+            })))),
+        }
+    }
 
-impl Stream for WS {
-    type Item = Result<Event, Error>;
+    pub fn writer(&self) -> WSWriter {
+        self.writer.clone()
+    }
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    /// Waits for the next complete message. Not cancel safe: a partially read frame is lost
+    /// when the future is dropped, so callers should drive it to completion.
+    pub async fn recv(&mut self) -> Result<Event, WebSocketError> {
+        let writer = self.writer.clone();
+        let mut send_fn = move |frame: Frame<'static>| {
+            let writer = writer.clone();
+            async move { writer.0.lock().await.write_frame(frame).await }
+        };
         loop {
-            match futures_util::ready!(self.0.stream.poll_flush()) {
-                Some(Ok(msg)) => {
-                    if let Some(msg) = Event::from_tungstenite(msg) {
-                        return Poll::Ready(Some(Ok(msg)));
-                    }
+            let frame: Frame<'static> = match &mut self.read {
+                Reader::Plain(read) => read.read_frame(&mut send_fn).await?,
+                Reader::Deflate(read) => read.read_frame(&mut send_fn).await?,
+            };
+            match frame.opcode {
+                OpCode::Text => return Ok(Event::Text(frame.payload)),
+                OpCode::Binary => return Ok(Event::Binary),
+                OpCode::Ping => {
+                    let pong = Frame::pong(frame.payload);
+                    self.writer.0.lock().await.write_frame(pong).await?;
+                    return Ok(Event::Ping);
+                }
+                OpCode::Pong => return Ok(Event::Pong),
+                OpCode::Close => {
+                    let (code, reason) = match frame.payload.len() {
+                        0 | 1 => (1005, String::new()),
+                        _ => (
+                            u16::from_be_bytes([frame.payload[0], frame.payload[1]]),
+                            String::from_utf8_lossy(&frame.payload[2..]).into_owned(),
+                        ),
+                    };
+                    return Ok(Event::Close { code, reason });
                 }
-                Some(Err(err)) => return Poll::Ready(Some(Err(Error::new(err)))),
-                None => return Poll::Ready(None),
+                OpCode::Continuation => continue,
             }
         }
     }
 }
 
-pub use web_socket;
-use web_socket::{Event, WebSocket};
+/// Write half of a `WS`, shared by everything that needs to send frames to the client.
+#[derive(Clone)]
+pub struct WSWriter(Arc<Mutex<Writer>>);
+
+impl WSWriter {
+    pub async fn send(&self, text: &str) -> Result<(), WebSocketError> {
+        let frame = Frame::text(Payload::Borrowed(text.as_bytes()));
+        self.0.lock().await.write_frame(frame).await
+    }
+
+    pub async fn close(&self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        let frame = Frame::close(code, reason.as_bytes());
+        self.0.lock().await.write_frame(frame).await
+    }
+}
+
+enum Writer {
+    Plain(WebSocketWrite<WriteHalf<Io>>),
+    Deflate(DeflateWriter),
+}
+
+impl Writer {
+    async fn write_frame(&mut self, frame: Frame<'_>) -> Result<(), WebSocketError> {
+        match self {
+            Writer::Plain(write) => write.write_frame(frame).await,
+            Writer::Deflate(write) => write.write_frame(frame.opcode, &frame.payload).await,
+        }
+    }
+}
+
+/// Writes frames itself, since fastwebsockets can't set the RSV1 bit that marks a message
+/// as compressed.
+struct DeflateWriter {
+    stream: WriteHalf<Io>,
+    deflater: Deflater,
+    closed: bool,
+    buffer: Vec<u8>,
+}
+
+impl DeflateWriter {
+    async fn write_frame(&mut self, opcode: OpCode, payload: &[u8]) -> Result<(), WebSocketError> {
+        if self.closed {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+        if opcode == OpCode::Close {
+            self.closed = true;
+        }
+        let compressed = match opcode {
+            OpCode::Text | OpCode::Binary => self.deflater.compress(payload)?,
+            _ => None,
+        };
+        let payload = compressed.as_deref().unwrap_or(payload);
+        self.buffer.clear();
+        FrameHeader::write(
+            &mut self.buffer,
+            opcode as u8,
+            compressed.is_some(),
+            payload.len(),
+        );
+        self.buffer.extend_from_slice(payload);
+        self.stream.write_all(&self.buffer).await?;
+        Ok(())
+    }
+}
+
+/// Why a request could not be upgraded to a WebSocket connection.
+#[derive(Debug)]
+pub enum UpgradeRejection {
+    MethodNotGet,
+    ConnectionNotUpgrade,
+    InvalidUpgradeHeader,
+    InvalidWebSocketVersion,
+    MissingWebSocketKey,
+    ConnectionNotUpgradable,
+    OriginNotAllowed,
+}
+
+impl UpgradeRejection {
+    fn status(&self) -> StatusCode {
+        match self {
+            UpgradeRejection::MethodNotGet
+            | UpgradeRejection::ConnectionNotUpgrade
+            | UpgradeRejection::MissingWebSocketKey => StatusCode::BAD_REQUEST,
+            UpgradeRejection::OriginNotAllowed => StatusCode::FORBIDDEN,
+            UpgradeRejection::InvalidUpgradeHeader
+            | UpgradeRejection::InvalidWebSocketVersion
+            | UpgradeRejection::ConnectionNotUpgradable => StatusCode::UPGRADE_REQUIRED,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            UpgradeRejection::MethodNotGet => "Request method must be `GET`",
+            UpgradeRejection::ConnectionNotUpgrade => "Connection header did not include `upgrade`",
+            UpgradeRejection::InvalidUpgradeHeader => {
+                "`Upgrade` header did not include `websocket`"
+            }
+            UpgradeRejection::InvalidWebSocketVersion => {
+                "`Sec-WebSocket-Version` header did not include `13`"
+            }
+            UpgradeRejection::MissingWebSocketKey => "`Sec-WebSocket-Key` header missing",
+            UpgradeRejection::ConnectionNotUpgradable => {
+                "WebSocket request couldn't be upgraded since no upgrade state was present"
+            }
+            UpgradeRejection::OriginNotAllowed => "Origin is not allowed to connect to this app",
+        }
+    }
+}
+
+impl IntoResponse for UpgradeRejection {
+    fn into_response(self) -> Response {
+        let mut builder = Response::builder().status(self.status());
+        // A 426 has to tell the client what to upgrade to (RFC 7231, section 6.5.15).
+        match self {
+            UpgradeRejection::InvalidUpgradeHeader | UpgradeRejection::ConnectionNotUpgradable => {
+                builder = builder
+                    .header(header::CONNECTION, "upgrade")
+                    .header(header::UPGRADE, "websocket");
+            }
+            UpgradeRejection::InvalidWebSocketVersion => {
+                builder = builder.header(header::SEC_WEBSOCKET_VERSION, "13");
+            }
+            _ => {}
+        }
+        builder
+            .body(axum::body::Body::from(self.message()))
+            .unwrap()
+    }
+}
 
 pub struct WebSocketUpgrade {
     sec_websocket_key: HeaderValue,
     on_upgrade: hyper::upgrade::OnUpgrade,
+    origin: Option<String>,
+    extensions: Option<String>,
+    deflate: Option<DeflateConfig>,
+    /// Subprotocols offered by the client, in its order of preference.
+    offered_protocols: Vec<String>,
+    protocol: Option<HeaderValue>,
 }
 
 impl WebSocketUpgrade {
+    /// The `Origin` the request was sent from. Browsers always set it; other clients may not.
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Selects the first subprotocol offered by the client that the server supports.
+    /// Without a match the connection goes ahead with no subprotocol.
+    pub fn protocols<I>(mut self, supported: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let supported: Vec<I::Item> = supported.into_iter().collect();
+        self.protocol = self
+            .offered_protocols
+            .iter()
+            .find(|offered| supported.iter().any(|s| s.as_ref() == offered.as_str()))
+            .and_then(|protocol| HeaderValue::from_str(protocol).ok());
+        self
+    }
+
+    /// Accepts the client's `permessage-deflate` offer, if it made an acceptable one.
+    pub fn permessage_deflate(mut self, threshold: usize) -> Self {
+        self.deflate = self
+            .extensions
+            .as_deref()
+            .and_then(|offers| DeflateConfig::negotiate(offers, threshold));
+        self
+    }
+
     pub fn on_upgrade<C, Fut>(self, callback: C) -> Response
-        where
-            C: FnOnce(WS) -> Fut + Send + 'static,
-            Fut: Future<Output=()> + Send + 'static,
+    where
+        C: FnOnce(WS) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
     {
         let on_upgrade = self.on_upgrade;
+        let deflate = self.deflate.clone();
         tokio::spawn(async move {
             let upgraded = match on_upgrade.await {
                 Ok(upgraded) => upgraded,
@@ -57,14 +305,14 @@ impl WebSocketUpgrade {
             };
             let upgraded = TokioIo::new(upgraded);
 
-            let socket = WS::server(upgraded);
+            let socket = WS::server(upgraded, deflate);
             callback(socket).await;
         });
 
         const UPGRADE: HeaderValue = HeaderValue::from_static("upgrade");
         const WEBSOCKET: HeaderValue = HeaderValue::from_static("websocket");
 
-        let builder = Response::builder()
+        let mut builder = Response::builder()
             .status(StatusCode::SWITCHING_PROTOCOLS)
             .header(header::CONNECTION, UPGRADE)
             .header(header::UPGRADE, WEBSOCKET)
@@ -72,6 +320,12 @@ impl WebSocketUpgrade {
                 header::SEC_WEBSOCKET_ACCEPT,
                 sign(self.sec_websocket_key.as_bytes()),
             );
+        if let Some(deflate) = &self.deflate {
+            builder = builder.header(header::SEC_WEBSOCKET_EXTENSIONS, deflate.response());
+        }
+        if let Some(protocol) = self.protocol {
+            builder = builder.header(header::SEC_WEBSOCKET_PROTOCOL, protocol);
+        }
 
         builder.body(axum::body::Body::empty()).unwrap()
     }
@@ -79,38 +333,67 @@ impl WebSocketUpgrade {
 
 #[async_trait]
 impl<S> FromRequestParts<S> for WebSocketUpgrade
-    where
-        S: Send + Sync,
+where
+    S: Send + Sync,
 {
-    type Rejection = ();
+    type Rejection = UpgradeRejection;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
         if parts.method != Method::GET {
-            return Err(());
+            return Err(UpgradeRejection::MethodNotGet);
         }
         if !header_contains(&parts.headers, header::CONNECTION, "upgrade") {
-            return Err(());
+            return Err(UpgradeRejection::ConnectionNotUpgrade);
         }
         if !header_eq(&parts.headers, header::UPGRADE, "websocket") {
-            return Err(());
+            return Err(UpgradeRejection::InvalidUpgradeHeader);
         }
         if !header_eq(&parts.headers, header::SEC_WEBSOCKET_VERSION, "13") {
-            return Err(());
+            return Err(UpgradeRejection::InvalidWebSocketVersion);
         }
         let sec_websocket_key = parts
             .headers
             .get(header::SEC_WEBSOCKET_KEY)
-            .ok_or(())?
+            .ok_or(UpgradeRejection::MissingWebSocketKey)?
             .clone();
 
         let on_upgrade = parts
             .extensions
             .remove::<hyper::upgrade::OnUpgrade>()
-            .ok_or(())?;
+            .ok_or(UpgradeRejection::ConnectionNotUpgradable)?;
+
+        let origin = parts
+            .headers
+            .get(header::ORIGIN)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let offers: Vec<&str> = parts
+            .headers
+            .get_all(header::SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        let extensions = (!offers.is_empty()).then(|| offers.join(","));
+
+        let offered_protocols = parts
+            .headers
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|protocol| protocol.trim().to_string())
+            .filter(|protocol| !protocol.is_empty())
+            .collect();
 
         Ok(Self {
             sec_websocket_key,
             on_upgrade,
+            origin,
+            extensions,
+            deflate: None,
+            offered_protocols,
+            protocol: None,
         })
     }
 }
@@ -0,0 +1,75 @@
+use crate::app::{App, WebhookInterface};
+use crate::log::Log;
+use crate::token::Token;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::sync::OnceLock;
+
+pub struct WebhookSender;
+
+impl WebhookSender {
+    pub fn send_cache_missed(app: &App, channel: &str) {
+        if !app.has_cache_missed_webhooks {
+            return;
+        }
+        Self::send(
+            app,
+            "cache_miss",
+            channel,
+            json!({
+                "name": "cache_miss",
+                "channel": channel,
+            }),
+        );
+    }
+
+    /// One client for every webhook, so deliveries share its connection pool.
+    fn client() -> &'static reqwest::Client {
+        static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+        CLIENT.get_or_init(reqwest::Client::new)
+    }
+
+    /// Posts the event to every webhook of the app subscribed to `event_type`, signed the
+    /// same way Pusher signs webhooks. Delivery happens in the background.
+    fn send(app: &App, event_type: &str, channel: &str, event: Value) {
+        let webhooks: Vec<WebhookInterface> = app
+            .webhooks
+            .iter()
+            .filter_map(|webhook| serde_json::from_value(webhook.clone()).ok())
+            .filter(|webhook: &WebhookInterface| {
+                webhook.event_types.iter().any(|t| t == event_type)
+                    && webhook.matches_channel(channel)
+            })
+            .collect();
+        if webhooks.is_empty() {
+            return;
+        }
+        let body = json!({
+            "time_ms": Utc::now().timestamp_millis(),
+            "events": [event],
+        })
+        .to_string();
+        let signature = Token::new(&app.key, &app.secret).sign(&body);
+        let app_key = app.key.clone();
+        tokio::spawn(async move {
+            for webhook in webhooks {
+                let url = match webhook.url {
+                    Some(url) => url,
+                    None => continue,
+                };
+                Log::webhook_sender_title(&format!("Sending webhook to {}", url));
+                let mut request = Self::client()
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .header("X-Pusher-Key", &app_key)
+                    .header("X-Pusher-Signature", &signature);
+                for (name, value) in webhook.headers.unwrap_or_default() {
+                    request = request.header(name, value);
+                }
+                if let Err(e) = request.body(body.clone()).send().await {
+                    Log::webhook_sender(&format!("Webhook to {} failed: {}", url, e));
+                }
+            }
+        });
+    }
+}
@@ -1,11 +1,24 @@
-use crate::channels::presence_channel_manager::PresenceMemberInfo;
+use crate::adapters::adapter::Adapter;
+use crate::app::App;
+use crate::channels::channel_manager::ChannelManager;
+use crate::channels::encrypted_private_channel_manager::EncryptedPrivateChannelManager;
+use crate::channels::presence_channel_manager::{PresenceChannelManager, PresenceMember};
+use crate::channels::private_channel_manager::PrivateChannelManager;
+use crate::channels::public_channel_manager::{JoinResponse, LeaveResponse, PublicChannelManager};
+use crate::limits::Limits;
 use crate::log::Log;
 use crate::message;
-use crate::message::{PusherMessage, UWebSocketMessage};
+use crate::message::{ClientEvent, ClientMessage, PusherMessage};
+use crate::metrics::metrics_trait::MetricsTrait;
+use crate::outbound_queue::{Outbound, OutboundQueue, SlowConsumerPolicy};
+use crate::protocol::Protocol;
+use crate::pusher_error::PusherError;
 use crate::server::Server;
+use crate::utils::Utils;
+use crate::web_socket::{Event, UpgradeRejection, WebSocketUpgrade, WS};
+use crate::webhook_sender::WebhookSender;
 use axum::extract::{ConnectInfo, Path, Query, State};
-use axum::response::IntoResponse;
-use echoxide::{WebSocketUpgrade, WS};
+use axum::response::{IntoResponse, Response};
 use rand::Rng;
 use serde::Serialize;
 use std::collections::HashMap;
@@ -13,11 +26,42 @@ use std::fmt::Debug;
 use std::hash::Hash;
 use std::net::SocketAddr;
 use std::sync::{Arc, Weak};
-use web_socket::Event;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+const DEFAULT_ACTIVITY_TIMEOUT: u64 = 120;
+
+/// How long a client has to answer a server-initiated `pusher:ping`.
+const PONG_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the writer gets to flush a closing connection's queue before it is dropped.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Wire encodings a client can ask for with `Sec-WebSocket-Protocol`. Messages are always JSON
+/// for now, so this only confirms what clients already get.
+const SUBPROTOCOLS: [&str; 1] = ["json"];
 
 #[derive(Debug, Clone)]
 pub struct User {
     pub id: String,
+    /// The decoded `user_data` the connection signed in with.
+    pub data: serde_json::Value,
+}
+
+impl User {
+    /// The user ids this user wants `online`/`offline` notifications about.
+    pub fn watchlist(&self) -> Vec<String> {
+        self.data
+            .get("watchlist")
+            .and_then(|watchlist| watchlist.as_array())
+            .map(|watchlist| {
+                watchlist
+                    .iter()
+                    .filter_map(|user_id| user_id.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 }
 
 impl Eq for WebSocket {}
@@ -28,13 +72,23 @@ impl PartialEq for WebSocket {
     }
 }
 
+/// Handle to a connected client. Clones share the same outbound queue, so the
+/// copy stored in the namespace can be written to by any broadcaster without
+/// waiting on the client.
+#[derive(Clone)]
 pub struct WebSocket {
-    pub(crate) ws: WS,
+    pub(crate) queue: Arc<OutboundQueue>,
     pub(crate) id: Option<String>,
     pub app_key: Option<String>,
     pub subscribed_channels: Option<Vec<String>>,
-    pub presence_channels: Option<HashMap<String, PresenceMemberInfo>>,
+    pub presence_channels: Option<HashMap<String, PresenceMember>>,
+    pub(crate) app: Option<App>,
     pub(crate) user: Option<User>,
+    pub(crate) client_events: Option<(Instant, i64)>,
+    pub(crate) protocol: &'static Protocol,
+    /// The client library and its version, as announced in the connection URL.
+    pub(crate) client: Option<String>,
+    pub(crate) client_version: Option<String>,
 }
 
 impl Hash for WebSocket {
@@ -44,20 +98,55 @@ impl Hash for WebSocket {
 }
 
 impl WebSocket {
-    pub fn new(ws: WS) -> Self {
+    pub fn new(queue: Arc<OutboundQueue>) -> Self {
         WebSocket {
-            ws,
+            queue,
             id: None,
             app_key: None,
             subscribed_channels: None,
             presence_channels: None,
+            app: None,
             user: None,
+            client_events: None,
+            protocol: Protocol::latest(),
+            client: None,
+            client_version: None,
+        }
+    }
+
+    /// The id of the app, which is also the namespace the socket lives in.
+    pub fn app_id(&self) -> String {
+        self.app
+            .as_ref()
+            .map(|app| app.id.clone())
+            .unwrap_or_default()
+    }
+
+    /// Counts a client event against the one-second window, returning false once the
+    /// app's limit is exceeded. A negative limit disables the check.
+    fn consume_client_event(&mut self, max_per_second: i64) -> bool {
+        if max_per_second < 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let (window_started, events) = self.client_events.get_or_insert((now, 0));
+        if now.duration_since(*window_started) >= Duration::from_secs(1) {
+            *window_started = now;
+            *events = 0;
         }
+        *events += 1;
+        *events <= max_per_second
     }
 
-    pub async fn send_json(&mut self, data: serde_json::Value) {
+    pub async fn send_json(&self, data: serde_json::Value) {
         Log::websocket_title("Sending message to client");
-        Log::websocket(serde_json::to_string_pretty(&data).unwrap().as_str());
+        let encrypted = data
+            .get("channel")
+            .and_then(|channel| channel.as_str())
+            .is_some_and(Utils::is_encrypted_private_channel);
+        if !encrypted {
+            Log::websocket(serde_json::to_string_pretty(&data).unwrap().as_str());
+        }
         let message = match serde_json::to_string(&data) {
             Ok(message) => message,
             Err(e) => {
@@ -65,13 +154,21 @@ impl WebSocket {
                 return;
             }
         };
-        self.ws
-            .send(message.as_str())
-            .await
-            .expect("TODO: panic message");
+        if !self.queue.push(Outbound::Message(message)) {
+            Log::websocket("Connection is already closed, dropping message");
+        }
     }
-    pub async fn stop(&mut self, socket: WS) {
-        socket.close("Closed").await.expect("TODO: panic message");
+
+    pub async fn stop(&self, code: u16, message: &str) {
+        self.queue.push(Outbound::Close(code, message.to_string()));
+    }
+
+    /// Reports the error to the client and closes the connection when its code requires it.
+    pub async fn send_error(&self, error: PusherError) {
+        self.send_json(error.to_json()).await;
+        if error.closes_connection() {
+            self.stop(error.code(), &error.to_string()).await;
+        }
     }
 }
 
@@ -85,7 +182,7 @@ impl WSHandler {
             server: Weak::new(),
         }
     }
-    pub async fn on_open(&mut self, ws: &mut WebSocket) {
+    pub async fn on_open(&self, ws: &mut WebSocket) {
         Log::websocket_title("WebSocket connection opened");
         ws.id = Some(Self::generate_socket_id());
         ws.subscribed_channels = Some(Vec::new());
@@ -93,24 +190,43 @@ impl WSHandler {
         ws.id = Some(Self::generate_socket_id());
         if let Some(server) = self.server.upgrade() {
             if server.closing {
-                ws.send_json(serde_json::json!({
-                    "event": "pusher:error",
-                    "data": serde_json::json!({
-                        "code": 4200,
-                        "message": "Server is closing. Please reconnect shortly.",
-                    }),
-                }))
-                .await;
+                ws.send_error(PusherError::ServerClosing).await;
+                return;
             }
         }
+        let mut data = serde_json::json!({
+            "socket_id": ws.id.as_ref().unwrap(),
+        });
+        if ws.protocol.sends_activity_timeout {
+            data["activity_timeout"] = serde_json::json!(Self::activity_timeout(ws).as_secs());
+        }
         let broadcast_message = serde_json::json!({
             "event": "pusher:connection_established",
-            "data": serde_json::json!({
-                "socket_id": ws.id.as_ref().unwrap(),
-                "activity_timeout": 120,
-            }),
+            "data": data,
         });
         ws.send_json(broadcast_message).await;
+        if let Some(server) = self.server.upgrade() {
+            let app_id = ws.app_id();
+            server
+                .adapter
+                .lock()
+                .await
+                .add_socket(&app_id, ws.clone())
+                .await;
+            let metrics = server.metrics.lock().await.clone();
+            if let Some(metrics) = metrics {
+                metrics.mark_new_connection(ws.clone());
+            }
+        }
+    }
+
+    /// The idle time, per app, after which the server checks the connection with `pusher:ping`.
+    fn activity_timeout(ws: &WebSocket) -> Duration {
+        let seconds = ws
+            .app
+            .as_ref()
+            .map_or(DEFAULT_ACTIVITY_TIMEOUT, |app| app.activity_timeout);
+        Duration::from_secs(seconds)
     }
 
     fn generate_socket_id() -> String {
@@ -127,39 +243,83 @@ impl WSHandler {
         format!("{}.{}", random_number(min, max), random_number(min, max))
     }
 
-    pub(crate) fn on_close(&mut self, code: u16, message: String) {
+    pub(crate) async fn on_close(&self, ws: &mut WebSocket, code: u16, message: String) {
         Log::websocket("❌ Connection closed:");
         Log::websocket(&format!("Code: {}", code));
         Log::websocket(&format!("Message: {}", message));
+        Log::websocket(&format!(
+            "Client: {} {} (protocol {})",
+            ws.client.as_deref().unwrap_or("unknown"),
+            ws.client_version.as_deref().unwrap_or_default(),
+            ws.protocol.version
+        ));
+        if ws.app.is_none() {
+            return;
+        }
+        let mut channels = ws.subscribed_channels.clone().unwrap_or_default();
+        for channel in ws.presence_channels.iter().flat_map(|p| p.keys()) {
+            if !channels.contains(channel) {
+                channels.push(channel.clone());
+            }
+        }
+        for channel in channels {
+            self.unsubscribe_from_channel(ws, &channel).await;
+        }
+        let server = match self.server.upgrade() {
+            Some(server) => server,
+            None => return,
+        };
+        let app_id = ws.app_id();
+        let ws_id = ws.id.clone().unwrap_or_default();
+        {
+            let mut adapter = server.adapter.lock().await;
+            adapter.remove_socket(&app_id, &ws_id).await;
+            if let Some(user) = ws.user.clone() {
+                adapter.remove_user(ws.clone()).await;
+                Self::send_watchlist_event(&mut *adapter, &app_id, &user.id, "offline").await;
+            }
+        }
+        let metrics = server.metrics.lock().await.clone();
+        if let Some(metrics) = metrics {
+            metrics.mark_disconnection(ws.clone());
+        }
     }
 
-    pub(crate) async fn on_message(&mut self, message: PusherMessage, ws: &mut WebSocket) {
+    pub(crate) async fn on_message(&self, message: PusherMessage, ws: &mut WebSocket) {
         Log::websocket_title("Received message from client");
-        match message.data {
+        let channel = message
+            .channel
+            .clone()
+            .or_else(|| message.data.as_ref().and_then(|data| data.channel.clone()));
+        let encrypted = channel
+            .as_deref()
+            .is_some_and(Utils::is_encrypted_private_channel);
+        match &message.data {
+            Some(_) if encrypted => {
+                Log::websocket("Encrypted channel payload");
+            }
             Some(data) => {
-                Log::websocket(serde_json::to_string_pretty(&data).unwrap().as_str());
+                Log::websocket(serde_json::to_string_pretty(data).unwrap().as_str());
             }
             None => {
                 Log::websocket("No data");
             }
         }
-        match message.event {
+        match message.event.clone() {
             Some(event) => match event.as_str() {
                 "pusher:subscribe" => {
                     Log::websocket("Subscribing to channel");
-                    ws.send_json(serde_json::json!({
-                        "event": "pusher_internal:subscription_succeeded",
-                        "data": serde_json::json!({}),
-                    }))
-                    .await;
+                    self.subscribe_to_channel(ws, message).await;
                 }
                 "pusher:unsubscribe" => {
-                    ws.send_json(serde_json::json!({
-                        "event": "pusher:unsubscribe",
-                        "data": serde_json::json!({}),
-                    }))
-                    .await;
                     Log::websocket("Unsubscribing from channel");
+                    if let Some(channel) = channel {
+                        self.unsubscribe_from_channel(ws, &channel).await;
+                    }
+                }
+                "pusher:signin" => {
+                    Log::websocket("Signing in user");
+                    self.handle_signin(ws, message).await;
                 }
                 "pusher:ping" => {
                     ws.send_json(serde_json::json!({
@@ -170,11 +330,6 @@ impl WSHandler {
                 }
                 "pusher:pong" => {
                     Log::websocket("Received pong");
-                    ws.send_json(serde_json::json!({
-                        "event": "pusher:ping",
-                        "data": serde_json::json!({}),
-                    }))
-                    .await;
                 }
                 _ => {
                     Log::websocket("No event");
@@ -186,64 +341,651 @@ impl WSHandler {
         }
     }
 
-    pub async fn handle_pong(&mut self) {
+    async fn join_channel(
+        &self,
+        ws: WebSocket,
+        channel: &str,
+        message: PusherMessage,
+    ) -> JoinResponse {
+        // `#` channels, such as `#server-to-user-{id}`, are only written to by the server.
+        if channel.starts_with('#') {
+            return JoinResponse::restricted_channel_name();
+        }
+        if let Some(app) = &ws.app {
+            if let Err(error) = Limits::check_channel_name(app, channel) {
+                return JoinResponse::limit_reached(4009, error.to_string());
+            }
+        }
+        if Utils::is_presence_channel(channel) {
+            PresenceChannelManager::new(self.server.clone())
+                .join(ws, channel, message)
+                .await
+        } else if Utils::is_encrypted_private_channel(channel) {
+            EncryptedPrivateChannelManager::new(self.server.clone())
+                .join(ws, channel, message)
+                .await
+        } else if Utils::is_private_channel(channel) {
+            PrivateChannelManager::new(self.server.clone())
+                .join(ws, channel, message)
+                .await
+        } else {
+            PublicChannelManager::new(self.server.clone())
+                .join(ws, channel, message)
+                .await
+        }
+    }
+
+    async fn leave_channel(&self, ws: WebSocket, channel: &str) -> LeaveResponse {
+        if Utils::is_presence_channel(channel) {
+            PresenceChannelManager::new(self.server.clone())
+                .leave(ws, channel)
+                .await
+        } else if Utils::is_encrypted_private_channel(channel) {
+            EncryptedPrivateChannelManager::new(self.server.clone())
+                .leave(ws, channel)
+                .await
+        } else if Utils::is_private_channel(channel) {
+            PrivateChannelManager::new(self.server.clone())
+                .leave(ws, channel)
+                .await
+        } else {
+            PublicChannelManager::new(self.server.clone())
+                .leave(ws, channel)
+                .await
+        }
+    }
+
+    async fn unsubscribe_from_channel(&self, ws: &mut WebSocket, channel: &str) {
+        let subscribed = ws
+            .subscribed_channels
+            .as_ref()
+            .is_some_and(|channels| channels.iter().any(|c| c == channel));
+        if !subscribed {
+            return;
+        }
+        let server = match self.server.upgrade() {
+            Some(server) => server,
+            None => return,
+        };
+        let response = self.leave_channel(ws.clone(), channel).await;
+        if let Some(subscribed_channels) = ws.subscribed_channels.as_mut() {
+            subscribed_channels.retain(|c| c != channel);
+        }
+        if let Some(presence_channels) = ws.presence_channels.as_mut() {
+            presence_channels.remove(channel);
+        }
+
+        let app_id = ws.app_id();
+        let mut adapter = server.adapter.lock().await;
+        adapter.add_socket(&app_id, ws.clone()).await;
+        if let Some(member) = response.member {
+            let member_removed = serde_json::json!({
+                "event": "pusher_internal:member_removed",
+                "channel": channel,
+                "data": {
+                    "user_id": member.user_id,
+                },
+            });
+            adapter
+                .send(
+                    &app_id,
+                    channel,
+                    &member_removed.to_string(),
+                    ws.id.as_deref(),
+                )
+                .await;
+        }
+    }
+
+    async fn subscribe_to_channel(&self, ws: &mut WebSocket, message: PusherMessage) {
+        let channel = match message.data.as_ref().and_then(|data| data.channel.clone()) {
+            Some(channel) => channel,
+            None => {
+                Log::websocket("No channel to subscribe to");
+                return;
+            }
+        };
+        let response = self.join_channel(ws.clone(), &channel, message).await;
+        if !response.success {
+            let data = if response.auth_error.unwrap_or(false) {
+                serde_json::json!({
+                    "type": "AuthError",
+                    "error": response.error_message,
+                    "status": 401,
+                })
+            } else {
+                serde_json::json!({
+                    "type": response.type_,
+                    "error": response.error_message,
+                    "status": response.error_code,
+                })
+            };
+            ws.send_json(serde_json::json!({
+                "event": "pusher:subscription_error",
+                "channel": channel,
+                "data": data,
+            }))
+            .await;
+            return;
+        }
+        let subscribed_channels = ws.subscribed_channels.get_or_insert_with(Vec::new);
+        if !subscribed_channels.contains(&channel) {
+            subscribed_channels.push(channel.clone());
+        }
+        let server = match self.server.upgrade() {
+            Some(server) => server,
+            None => return,
+        };
+        let app_id = ws.app_id();
+        let member = match response.member {
+            Some(member) => member,
+            None => {
+                // The namespace keeps its own copy of the socket, refresh it with the new state.
+                server
+                    .adapter
+                    .lock()
+                    .await
+                    .add_socket(&app_id, ws.clone())
+                    .await;
+                ws.send_json(serde_json::json!({
+                    "event": "pusher_internal:subscription_succeeded",
+                    "channel": channel,
+                    "data": serde_json::json!({}),
+                }))
+                .await;
+                self.replay_cached_event(ws, &server, &channel).await;
+                return;
+            }
+        };
+
+        // The presence channel manager registered the member and announced it to the channel.
+        let mut adapter = server.adapter.lock().await;
+        ws.presence_channels
+            .get_or_insert_with(HashMap::new)
+            .insert(channel.clone(), member.clone());
+        adapter.add_socket(&app_id, ws.clone()).await;
+        let members = adapter.get_channel_members(&app_id, &channel, false).await;
+        ws.send_json(serde_json::json!({
+            "event": "pusher_internal:subscription_succeeded",
+            "channel": channel,
+            "data": serde_json::json!({
+                "presence": {
+                    "ids": members.keys().collect::<Vec<_>>(),
+                    "hash": members
+                        .iter()
+                        .map(|(user_id, user_info)| {
+                            (user_id.clone(), serde_json::json!(user_info.data))
+                        })
+                        .collect::<serde_json::Map<_, _>>(),
+                    "count": members.len(),
+                },
+            }),
+        }))
+        .await;
+        self.replay_cached_event(ws, &server, &channel).await;
+    }
+
+    /// Sends the last event of a cache channel to a new subscriber, or reports the miss.
+    async fn replay_cached_event(&self, ws: &WebSocket, server: &Server, channel: &str) {
+        if !Utils::is_caching_channel(channel) {
+            return;
+        }
+        match server.cached_event(&ws.app_id(), channel).await {
+            Some(event) => ws.send_json(event).await,
+            None => {
+                ws.send_json(serde_json::json!({
+                    "event": "pusher:cache_miss",
+                    "channel": channel,
+                }))
+                .await;
+                if let Some(app) = &ws.app {
+                    WebhookSender::send_cache_missed(app, channel);
+                }
+            }
+        }
+    }
+
+    async fn handle_client_event(&self, ws: &mut WebSocket, message: ClientEvent) {
+        Log::websocket_title("Received client event");
+        let channel = message.channel.clone().unwrap_or_default();
+        let event = message.event.clone();
+        let server = match self.server.upgrade() {
+            Some(server) => server,
+            None => return,
+        };
+        let app_id = ws.app_id();
+        let app = match ws.app.clone() {
+            Some(app) => app,
+            None => return,
+        };
+        let data = match &message.data {
+            serde_json::Value::String(data) => data.clone(),
+            data => data.to_string(),
+        };
+        if let Err(error) = Limits::check_event(&app, std::slice::from_ref(&channel), &event, &data)
+        {
+            ws.send_error(error.into()).await;
+            return;
+        }
+        let rejection = if !app.enable_client_messages {
+            Some("The app does not have client messaging enabled.")
+        } else if Utils::is_encrypted_private_channel(&channel) {
+            Some("Client events are not supported on encrypted channels.")
+        } else if !Utils::is_private_channel(&channel) {
+            Some("Client events can only be sent on private and presence channels.")
+        } else if !server
+            .adapter
+            .lock()
+            .await
+            .is_in_channel(
+                &app_id,
+                &channel,
+                ws.id.as_deref().unwrap_or_default(),
+                false,
+            )
+            .await
+        {
+            Some("The client is not subscribed to the channel.")
+        } else if !ws.consume_client_event(app.max_client_events_per_second) {
+            Some("The rate limit for sending client events exceeded the quota.")
+        } else {
+            None
+        };
+        if let Some(rejection) = rejection {
+            ws.send_error(PusherError::ClientEventRejected(rejection.to_string()))
+                .await;
+            return;
+        }
+
+        let mut client_event = serde_json::json!({
+            "event": event,
+            "channel": channel,
+            "data": message.data,
+        });
+        let member = ws
+            .presence_channels
+            .as_ref()
+            .and_then(|presence_channels| presence_channels.get(&channel));
+        if let Some(member) = member {
+            client_event["user_id"] = serde_json::json!(member.user_id);
+        }
+        if Utils::is_caching_channel(&channel) {
+            server.cache_event(&app_id, &channel, &client_event).await;
+        }
+        server
+            .adapter
+            .lock()
+            .await
+            .send(
+                &app_id,
+                &channel,
+                &client_event.to_string(),
+                ws.id.as_deref(),
+            )
+            .await;
+    }
+
+    /// Binds the user from a signed `pusher:signin` message to the connection.
+    async fn handle_signin(&self, ws: &mut WebSocket, message: PusherMessage) {
+        let app = match ws.app.clone() {
+            Some(app) => app,
+            None => return,
+        };
+        if !ws.protocol.supports_user_authentication {
+            ws.send_error(PusherError::InvalidMessage(format!(
+                "Protocol {} does not support user authentication.",
+                ws.protocol.version
+            )))
+            .await;
+            return;
+        }
+        let user_data = message
+            .data
+            .as_ref()
+            .and_then(|data| data.user_data.clone())
+            .unwrap_or_default();
+        let data_to_sign = format!(
+            "{}::user::{}",
+            ws.id.as_deref().unwrap_or_default(),
+            user_data
+        );
+        let authorized = PrivateChannelManager::signature_is_valid(
+            &app,
+            &PrivateChannelManager::passed_signature(&message),
+            &data_to_sign,
+        );
+        let user = match Self::parse_user(&user_data) {
+            Some(user) if authorized => user,
+            _ => {
+                ws.send_error(PusherError::Unauthorized).await;
+                return;
+            }
+        };
+        let server = match self.server.upgrade() {
+            Some(server) => server,
+            None => return,
+        };
+        let mut adapter = server.adapter.lock().await;
+        if let Some(previous_user) = ws.user.clone().filter(|previous| previous.id != user.id) {
+            adapter.remove_user(ws.clone()).await;
+            Self::send_watchlist_event(&mut *adapter, &app.id, &previous_user.id, "offline").await;
+        }
+        let first_socket = adapter.get_user_sockets(&app.id, &user.id).await.is_empty();
+        let (user_id, watchlist) = (user.id.clone(), user.watchlist());
+        ws.user = Some(user);
+        adapter.add_user(ws.clone()).await;
+        adapter.add_socket(&app.id, ws.clone()).await;
+        ws.send_json(serde_json::json!({
+            "event": "pusher:signin_success",
+            "data": { "user_data": user_data },
+        }))
+        .await;
+
+        if !watchlist.is_empty() {
+            let (mut online, mut offline) = (Vec::new(), Vec::new());
+            for watched_id in watchlist {
+                if adapter
+                    .get_user_sockets(&app.id, &watched_id)
+                    .await
+                    .is_empty()
+                {
+                    offline.push(watched_id);
+                } else {
+                    online.push(watched_id);
+                }
+            }
+            ws.send_json(serde_json::json!({
+                "event": "pusher_internal:watchlist_events",
+                "data": {
+                    "events": [
+                        { "name": "online", "user_ids": online },
+                        { "name": "offline", "user_ids": offline },
+                    ],
+                },
+            }))
+            .await;
+        }
+        if first_socket {
+            Self::send_watchlist_event(&mut *adapter, &app.id, &user_id, "online").await;
+        }
+    }
+
+    /// Tells every socket watching `user_id` that the user came `online` or went `offline`.
+    async fn send_watchlist_event(
+        adapter: &mut impl Adapter,
+        app_id: &str,
+        user_id: &str,
+        name: &str,
+    ) {
+        if name == "offline" && !adapter.get_user_sockets(app_id, user_id).await.is_empty() {
+            return;
+        }
+        let event = serde_json::json!({
+            "event": "pusher_internal:watchlist_events",
+            "data": {
+                "events": [{ "name": name, "user_ids": [user_id] }],
+            },
+        });
+        for (_ws_id, ws) in adapter.get_watcher_sockets(app_id, user_id).await {
+            ws.send_json(event.clone()).await;
+        }
+    }
+
+    fn parse_user(user_data: &str) -> Option<User> {
+        let data: serde_json::Value = serde_json::from_str(user_data).ok()?;
+        let id = data.get("id")?.as_str()?.to_string();
+        if id.is_empty() {
+            return None;
+        }
+        Some(User { id, data })
+    }
+
+    pub async fn handle_pong(&self) {
         Log::websocket_title("Received pong");
     }
 
-    pub async fn handle_ping(&mut self) {
+    pub async fn handle_ping(&self) {
         Log::websocket_title("Received ping");
     }
 
-    pub async fn handle_socket(socket: WS, who: SocketAddr) {
+    pub async fn handle_socket(
+        self: Arc<Self>,
+        mut socket: WS,
+        who: SocketAddr,
+        query: PusherWebsocketQuery,
+        admission: Result<(App, &'static Protocol), PusherError>,
+    ) {
         println!("New WebSocket connection: {}", who);
-        let mut ws = WebSocket::new(socket);
-        let mut ws_handler = WSHandler::new();
-        ws_handler.on_open(&mut ws).await;
-        while let Ok(ev) = ws.ws.recv().await {
-            match ev {
-                Event::Data { ty, data } => {
-                    println!("Data: {:#?}", ty);
-                    let data = String::from_utf8(data.to_vec()).unwrap();
-                    let pusher_message: PusherMessage = serde_json::from_str(&data).unwrap();
-                    ws_handler.on_message(pusher_message, &mut ws).await;
+        // Rejected connections only get their error before closing, so they aren't measured.
+        let queue = match (&admission, self.server.upgrade()) {
+            (Ok((app, _)), Some(server)) => server.outbound_queue(app.id.clone()).await,
+            _ => OutboundQueue::new(1000, SlowConsumerPolicy::Close, String::new(), None),
+        };
+        let queue = Arc::new(queue);
+        let mut ws = WebSocket::new(queue.clone());
+        ws.client = query.client;
+        ws.client_version = query.version;
+        match admission {
+            Ok((app, protocol)) => {
+                ws.protocol = protocol;
+                ws.app_key = Some(app.key.clone());
+                ws.app = Some(app);
+                self.on_open(&mut ws).await;
+            }
+            Err(error) => ws.send_error(error).await,
+        }
+        let activity_timeout = Self::activity_timeout(&ws);
+        let auth_deadline = match (&ws.app, self.server.upgrade()) {
+            (Some(app), Some(server)) if app.enable_user_authentication => {
+                Some(Instant::now() + server.user_authentication_timeout())
+            }
+            _ => None,
+        };
+        let writer = socket.writer();
+        // Frames are written on their own task, so a slow client only ever backs up its queue.
+        let writer_queue = queue.clone();
+        let mut writer_task = tokio::spawn(async move {
+            while let Some(frame) = writer_queue.pop().await {
+                match frame {
+                    Outbound::Message(message) => {
+                        if writer.send(&message).await.is_err() {
+                            return None;
+                        }
+                    }
+                    Outbound::Close(code, message) => {
+                        let _ = writer.close(code, &message).await;
+                        return Some((code, message));
+                    }
                 }
-                Event::Ping(_) => {
-                    ws_handler.handle_ping().await;
+            }
+            None
+        });
+        // `recv` loses data when cancelled, so frames are read on their own task.
+        let (events_tx, mut events) = mpsc::channel(1);
+        let reader = tokio::spawn(async move {
+            loop {
+                let event = socket.recv().await;
+                let done = matches!(event, Err(_) | Ok(Event::Close { .. }));
+                if events_tx.send(event).await.is_err() || done {
+                    break;
+                }
+            }
+        });
+        let mut close_code = 1006;
+        let mut close_reason = String::from("Connection lost");
+        let mut last_activity = Instant::now();
+        let mut ping_sent: Option<Instant> = None;
+        let mut closing = false;
+        let mut writer_done = false;
+        loop {
+            let deadline = match ping_sent {
+                Some(ping_sent) => ping_sent + PONG_TIMEOUT,
+                None => last_activity + activity_timeout,
+            };
+            tokio::select! {
+                event = events.recv() => {
+                    let event = match event {
+                        Some(Ok(event)) => event,
+                        Some(Err(e)) => {
+                            Log::websocket(&format!("Error: {}", e));
+                            break;
+                        }
+                        None => break,
+                    };
+                    last_activity = Instant::now();
+                    ping_sent = None;
+                    match event {
+                        Event::Text(data) => match Self::parse_message(&data) {
+                            Ok(ClientMessage::Pusher(message)) => {
+                                self.on_message(message, &mut ws).await
+                            }
+                            Ok(ClientMessage::ClientEvent(message)) => {
+                                self.handle_client_event(&mut ws, message).await
+                            }
+                            Err(error) => ws.send_error(error).await,
+                        },
+                        Event::Binary => {
+                            ws.send_error(PusherError::InvalidMessage(
+                                "Binary frames are not supported.".to_string(),
+                            ))
+                            .await;
+                        }
+                        Event::Ping => {
+                            self.handle_ping().await;
+                        }
+                        Event::Pong => {
+                            self.handle_pong().await;
+                        }
+                        Event::Close { code, reason } => {
+                            close_code = code;
+                            close_reason = reason;
+                            break;
+                        }
+                    }
                 }
-                Event::Pong(_) => {
-                    ws_handler.handle_pong().await;
+                written = &mut writer_task => {
+                    writer_done = true;
+                    if let Ok(Some((code, message))) = written {
+                        close_code = code;
+                        close_reason = message;
+                    }
+                    break;
                 }
-                Event::Error(_) => {
-                    Log::websocket("Error");
+                _ = tokio::time::sleep_until(auth_deadline.unwrap_or(deadline).into()),
+                    if auth_deadline.is_some() && ws.user.is_none() && !closing => {
+                    ws.send_error(PusherError::AuthenticationTimeout).await;
+                    closing = true;
                 }
-                Event::Close { .. } => {
-                    Log::websocket_title("❌ Connection closed:");
+                _ = tokio::time::sleep_until(deadline.into()), if !closing => {
+                    if ping_sent.is_some() {
+                        ws.send_error(PusherError::PongNotReceived).await;
+                        closing = true;
+                        continue;
+                    }
+                    ws.send_json(serde_json::json!({
+                        "event": "pusher:ping",
+                        "data": serde_json::json!({}),
+                    }))
+                    .await;
+                    ping_sent = Some(Instant::now());
                 }
             }
         }
+        queue.shutdown();
+        if !writer_done
+            && tokio::time::timeout(DRAIN_TIMEOUT, &mut writer_task)
+                .await
+                .is_err()
+        {
+            writer_task.abort();
+        }
+        reader.abort();
+        self.on_close(&mut ws, close_code, close_reason).await;
+    }
+
+    /// Parses a text frame. Client events are kept apart so their data stays untouched.
+    fn parse_message(data: &[u8]) -> Result<ClientMessage, PusherError> {
+        let invalid = |_| {
+            PusherError::InvalidMessage("The message is not a valid Pusher message.".to_string())
+        };
+        let message: serde_json::Value = serde_json::from_slice(data).map_err(invalid)?;
+        let is_client_event = message
+            .get("event")
+            .and_then(serde_json::Value::as_str)
+            .is_some_and(Utils::is_client_event);
+        if is_client_event {
+            serde_json::from_value(message)
+                .map(ClientMessage::ClientEvent)
+                .map_err(invalid)
+        } else {
+            serde_json::from_value(message)
+                .map(ClientMessage::Pusher)
+                .map_err(invalid)
+        }
+    }
+
+    /// Resolves the app a client connects to and checks it can take one more connection.
+    async fn resolve_app(&self, app_key: &str) -> Result<App, PusherError> {
+        let server = self.server.upgrade().ok_or(PusherError::ServerClosing)?;
+        let app = server
+            .find_app_by_key(app_key)
+            .ok_or(PusherError::AppNotFound)?;
+        if !app.enabled {
+            return Err(PusherError::AppDisabled);
+        }
+        if app.max_connections >= 0 {
+            let connections = server
+                .adapter
+                .lock()
+                .await
+                .get_sockets_count(&app.id, false)
+                .await;
+            if connections as i64 >= app.max_connections {
+                return Err(PusherError::OverCapacity);
+            }
+        }
+        Ok(app)
     }
 
     pub async fn ws_handler(
-        Path(app_id): Path<String>,
+        self: Arc<Self>,
+        Path(app_key): Path<String>,
         query: Query<PusherWebsocketQuery>,
         ws: WebSocketUpgrade,
         ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    ) -> impl IntoResponse {
+    ) -> Response {
         Log::info(format!(
             "WebSocket connection for app {}. Protocol: {}, client: {}, version: {}, flash: {}",
-            app_id,
-            query.protocol.unwrap_or(0),
+            app_key,
+            query.protocol.as_deref().unwrap_or(""),
             query.client.as_deref().unwrap_or(""),
             query.version.as_deref().unwrap_or(""),
             query.flash.unwrap_or(false)
         ));
-        ws.on_upgrade(move |socket| WSHandler::handle_socket(socket, addr))
+        let admission = match Protocol::negotiate(query.protocol.as_deref()) {
+            Ok(protocol) => self.resolve_app(&app_key).await.map(|app| (app, protocol)),
+            Err(error) => Err(error),
+        };
+        if let (Ok((app, _)), Some(origin)) = (&admission, ws.origin()) {
+            if !app.allows_origin(origin) {
+                Log::info(format!("Rejected origin {} for app {}", origin, app.id));
+                return UpgradeRejection::OriginNotAllowed.into_response();
+            }
+        }
+        let ws = ws.protocols(SUBPROTOCOLS);
+        let ws = match &admission {
+            Ok((app, _)) if app.enable_compression => {
+                ws.permessage_deflate(app.compression_threshold as usize)
+            }
+            _ => ws,
+        };
+        ws.on_upgrade(move |socket| self.handle_socket(socket, addr, query.0, admission))
     }
 }
 
 #[derive(Debug, serde::Deserialize, Serialize)]
 pub struct PusherWebsocketQuery {
-    protocol: Option<u8>,
+    protocol: Option<String>,
     client: Option<String>,
     version: Option<String>,
     flash: Option<bool>,